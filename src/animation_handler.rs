@@ -1,4 +1,5 @@
 use crate::messages::{Animation, AnimationFrame};
+use crate::output;
 use crate::Result;
 use image::bmp::BMPDecoder;
 use image::ImageDecoder;
@@ -9,6 +10,7 @@ use std::str;
 
 pub struct AnimationHandler {
     pub animations: HashMap<String, Animation>,
+    next_id: u64,
 }
 
 impl Default for AnimationHandler {
@@ -31,6 +33,7 @@ impl AnimationHandler {
         let _ = fs::create_dir("animations");
         let mut handler = AnimationHandler {
             animations: HashMap::new(),
+            next_id: 0,
         };
         for file in fs::read_dir("animations")? {
             let file = file?;
@@ -60,6 +63,8 @@ impl AnimationHandler {
     pub fn load(&mut self, name: &str, map: &HashMap<String, Vec<u8>>) -> Result<()> {
         let mut animation = Animation::default();
         animation.name = name.to_owned();
+        animation.id = self.next_id;
+        self.next_id += 1;
 
         let mut frames = Vec::new();
 
@@ -84,6 +89,7 @@ impl AnimationHandler {
                 None => bail!("Missing frame {}", index),
             });
         }
+        animation.payloads = animation.frames.iter().map(output::encode).collect();
         self.animations.insert(name.to_owned(), animation);
         Ok(())
     }
@@ -102,14 +108,8 @@ impl AnimationHandler {
         for (index, chunk) in image.chunks(21).enumerate() {
             let mut row: [(u8, u8, u8); 7] = [(0u8, 0u8, 0u8); 7];
             for (index, slice) in chunk.chunks(3).enumerate() {
-                // Normalize to 0-150 instead of 0-255 because the torches can overheat
-                let r = (u16::from(slice[0]) * 100 / 255) as u8;
-                let g = (u16::from(slice[1]) * 100 / 255) as u8;
-                let b = (u16::from(slice[2]) * 100 / 255) as u8;
-                assert!(r <= 100);
-                assert!(g <= 100);
-                assert!(b <= 100);
-                row[index] = (r, g, b);
+                // Brightness is limited in the output stage, see `OutputStage::apply`
+                row[index] = (slice[0], slice[1], slice[2]);
             }
             result[index] = row;
         }
@@ -1,68 +1,191 @@
+use super::{read_name, NodeMetrics};
 use crate::messages::Node;
 use crate::Result;
 use artnet_protocol::PollReply;
+use bytes::Bytes;
 use failure::ResultExt;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
-use std::str;
 
 pub struct Client {
     pub socket_address: SocketAddr,
     pub addr: [u8; 4],
     pub addr_string: String,
+    pub mac: [u8; 6],
+    pub mac_string: String,
     pub last_reply_received: f64,
     pub short_name: String,
     pub long_name: String,
     pub current: RenderMode,
     pub millis_since_last_frame: usize,
     pub current_animation_frame: usize,
+    /// The DMX data of the last ArtDmx sent, unchanged frames are not sent again until `keepalive`
+    pub last_frame: Option<Bytes>,
+    pub last_frame_sent: f64,
+    /// The 15-bit Port-Address of the first output port
+    pub universe: u16,
+    pub dhcp: bool,
+    /// A change sent with ArtAddress or ArtIpProg that no PollReply has confirmed yet
+    pub pending: Option<PendingChange>,
+    pub change: Option<ChangeStatus>,
+    pub metrics: NodeMetrics,
+}
+
+/// The settings a node should report after an ArtAddress or ArtIpProg, `None` for unchanged ones
+#[derive(Debug, Default, Clone)]
+pub struct PendingChange {
+    pub sent: f64,
+    pub short_name: Option<String>,
+    pub long_name: Option<String>,
+    pub universe: Option<u16>,
+    pub address: Option<String>,
+    pub dhcp: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize)]
+pub enum ChangeStatus {
+    Pending,
+    Confirmed,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RenderMode {
     Color(u8, u8, u8),
     Animation(String),
+    /// Fills the torch from the bottom up with the audio level
+    Vu(u8, u8, u8),
+    /// Flashes the whole torch on every beat
+    BeatFlash(u8, u8, u8),
+    /// A bar for every frequency band, low frequencies on the left
+    Spectrum,
+    /// An animation that advances a frame on every beat instead of at its own fps
+    BeatAnimation(String),
+    /// An animation that advances the given amount of frames per beat of the tempo clock
+    TempoAnimation(String, u8),
+    /// Pixels from ArtDmx packets on the given universe, merged with the given local mode.
+    /// Only the local mode is shown when the stream stops.
+    External(u16, Box<RenderMode>),
+    /// An animation that plays once, after which the torch returns to the given mode
+    OneShot(String, Box<RenderMode>),
 }
 
 impl Client {
-    pub fn new(socket_address: SocketAddr, reply: &PollReply) -> Result<Client> {
-        let short_name_index = reply
-            .short_name
-            .iter()
-            .position(|b| *b == 0)
-            .unwrap_or_else(|| reply.short_name.len());
-        let long_name_index = reply
-            .long_name
-            .iter()
-            .position(|b| *b == 0)
-            .unwrap_or_else(|| reply.long_name.len());
-
-        let short_name = str::from_utf8(&reply.short_name[..short_name_index])
-            .context("Could not get short_name")?
-            .to_owned();
-        let long_name = str::from_utf8(&reply.long_name[..long_name_index])
-            .context("Could not get long_name")?
-            .to_owned();
-
+    pub fn new(socket_address: SocketAddr, reply: &PollReply, mode: RenderMode) -> Result<Client> {
         Ok(Client {
             socket_address,
             addr: reply.address.octets(),
             addr_string: format!("{}", reply.address),
-            short_name,
-            long_name,
+            mac: reply.mac,
+            mac_string: reply
+                .mac
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(":"),
+            short_name: read_name(&reply.short_name).context("Could not get short_name")?,
+            long_name: read_name(&reply.long_name).context("Could not get long_name")?,
             last_reply_received: 0.,
-            current: RenderMode::Color(0, 100, 0),
+            current: mode,
             millis_since_last_frame: 0,
             current_animation_frame: 0,
+            last_frame: None,
+            last_frame_sent: 0.,
+            universe: universe(reply),
+            dhcp: reply.status_2 & 0x02 != 0,
+            pending: None,
+            change: None,
+            metrics: NodeMetrics::default(),
         })
     }
 
+    /// Takes over the settings the node reports, and confirms a pending change if they match it
+    pub fn update(&mut self, reply: &PollReply) -> Result<()> {
+        self.short_name = read_name(&reply.short_name).context("Could not get short_name")?;
+        self.long_name = read_name(&reply.long_name).context("Could not get long_name")?;
+        self.universe = universe(reply);
+        self.dhcp = reply.status_2 & 0x02 != 0;
+        if let Some(pending) = &self.pending {
+            if self.mismatches(pending).is_empty() {
+                println!("Torch {} confirmed the change", self.addr_string);
+                self.pending = None;
+                self.change = Some(ChangeStatus::Confirmed);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_pending(&mut self, pending: PendingChange) {
+        self.pending = Some(pending);
+        self.change = Some(ChangeStatus::Pending);
+    }
+
+    /// Fails a pending change that was not confirmed within `timeout` seconds
+    pub fn check_pending(&mut self, now: f64, timeout: f64) {
+        let pending = match &self.pending {
+            Some(pending) if now - pending.sent >= timeout => pending,
+            _ => return,
+        };
+        let error = format!("Not applied: {}", self.mismatches(pending).join(", "));
+        println!(
+            "Torch {} did not confirm the change: {}",
+            self.addr_string, error
+        );
+        self.pending = None;
+        self.change = Some(ChangeStatus::Failed(error));
+    }
+
+    fn mismatches(&self, pending: &PendingChange) -> Vec<&'static str> {
+        let mut mismatches = Vec::new();
+        if pending.short_name.iter().any(|n| *n != self.short_name) {
+            mismatches.push("short_name");
+        }
+        if pending.long_name.iter().any(|n| *n != self.long_name) {
+            mismatches.push("long_name");
+        }
+        if pending.universe.iter().any(|u| *u != self.universe) {
+            mismatches.push("universe");
+        }
+        if pending.address.iter().any(|a| *a != self.addr_string) {
+            mismatches.push("address");
+        }
+        if pending.dhcp.iter().any(|d| *d != self.dhcp) {
+            mismatches.push("dhcp");
+        }
+        mismatches
+    }
+
+    /// Returns the MAC address of this node, if the node supplied one
+    pub fn mac(&self) -> Option<&str> {
+        if self.mac == [0; 6] {
+            None
+        } else {
+            Some(&self.mac_string)
+        }
+    }
+
+    pub fn is_online(&self, now: f64, timeout: f64) -> bool {
+        now - self.last_reply_received < timeout
+    }
+
     pub fn get_node(&self) -> Node {
         Node {
             ip: self.addr_string.clone(),
             short_name: self.short_name.clone(),
             long_name: self.long_name.clone(),
             current: self.current.clone(),
+            flash_violations: 0,
+            universe: self.universe,
+            change: self.change.clone(),
+            rdm: Vec::new(),
+            metrics: self.metrics.status(),
         }
     }
 }
+
+/// The Port-Address of the first output port
+fn universe(reply: &PollReply) -> u16 {
+    u16::from(reply.port_address[0] & 0x7F) << 8
+        | u16::from(reply.port_address[1] & 0x0F) << 4
+        | u16::from(reply.swout[0] & 0x0F)
+}
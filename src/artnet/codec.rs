@@ -1,40 +1,218 @@
+use super::log::RateLimitedLog;
+use super::Packet;
 use crate::Result;
 use artnet_protocol::ArtCommand;
 use bytes::BytesMut;
 use failure::Error;
-use tokio_codec::{Decoder, Encoder};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use tokio_codec::Decoder;
+
+const ARTNET_HEADER: &[u8] = b"Art-Net\0";
+/// Sources beyond this are counted together, so a flood of spoofed addresses can not use up memory
+const MAX_SOURCES: usize = 256;
 
 #[derive(Default)]
 pub struct Codec {}
 
+/// A received datagram
+pub enum Decoded {
+    Packet(Packet),
+    /// A valid Art-Net packet with an opcode that is not handled
+    UnknownOpcode(u16),
+    /// Not Art-Net, or an Art-Net packet that is too short or corrupt
+    Malformed(Error),
+}
+
+/// Every datagram is decoded into an item, `None` would end the stream of received packets
 impl Decoder for Codec {
-    type Item = ArtCommand;
+    type Item = Decoded;
     type Error = Error;
 
     fn decode(&mut self, bytes: &mut BytesMut) -> Result<Option<Self::Item>> {
-        Ok(match ArtCommand::from_buffer(&bytes) {
-            Ok(c) => Some(c),
-            Err(e) => {
-                println!("Could not decode bytes: {:?}", e);
-                None
+        let opcode = if bytes.len() >= 10 && &bytes[..8] == ARTNET_HEADER {
+            Some(u16::from_le_bytes([bytes[8], bytes[9]]))
+        } else {
+            None
+        };
+        Ok(Some(match (Packet::from_buffer(bytes), opcode) {
+            // `artnet_protocol` knows these opcodes but can not decode them
+            (Ok(Packet::Command(command)), Some(opcode)) if !is_decoded(&command) => {
+                Decoded::UnknownOpcode(opcode)
             }
-        })
+            (Ok(packet), _) => Decoded::Packet(packet),
+            (Err(e), Some(opcode)) => match e.downcast_ref::<artnet_protocol::Error>() {
+                Some(artnet_protocol::Error::UnknownOpcode(_)) => Decoded::UnknownOpcode(opcode),
+                _ => Decoded::Malformed(e),
+            },
+            (Err(e), None) => Decoded::Malformed(e),
+        }))
     }
 }
 
-impl Encoder for Codec {
-    type Item = ArtCommand;
-    type Error = Error;
+fn is_decoded(command: &ArtCommand) -> bool {
+    matches!(
+        command,
+        ArtCommand::Poll(_) | ArtCommand::PollReply(_) | ArtCommand::Output(_)
+    )
+}
 
-    fn encode(&mut self, item: ArtCommand, bytes: &mut BytesMut) -> Result<()> {
-        let buffer = match item.into_buffer() {
-            Ok(b) => b,
-            Err(e) => {
-                // Should never happen
-                panic!("Could not encode ArtCommand: {:?}", e);
-            }
+/// Counts the received datagrams of every source
+#[derive(Default)]
+pub struct DecodeStats {
+    sources: HashMap<IpAddr, SourceStats>,
+    /// The sources beyond `MAX_SOURCES`
+    other: SourceStats,
+    log: RateLimitedLog,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceStats {
+    pub decoded: u64,
+    pub unknown_opcode: u64,
+    pub malformed: u64,
+    /// The amount of packets of every unknown opcode, like `0x5200`
+    pub unknown_opcodes: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DecodeStatus {
+    pub decoded: u64,
+    pub unknown_opcode: u64,
+    pub malformed: u64,
+    /// By IP, with `other` for the sources beyond the first 256
+    pub sources: BTreeMap<String, SourceStats>,
+}
+
+impl DecodeStats {
+    /// Counts a datagram and returns the packet in it, if any
+    pub fn record(&mut self, addr: SocketAddr, decoded: Decoded, now: f64) -> Option<Packet> {
+        let ip = addr.ip();
+        let stats = if self.sources.len() < MAX_SOURCES || self.sources.contains_key(&ip) {
+            self.sources.entry(ip).or_default()
+        } else {
+            &mut self.other
         };
-        bytes.extend_from_slice(&buffer);
-        Ok(())
+        match decoded {
+            Decoded::Packet(packet) => {
+                stats.decoded += 1;
+                return Some(packet);
+            }
+            Decoded::UnknownOpcode(opcode) => {
+                stats.unknown_opcode += 1;
+                let opcode = format!("0x{:04X}", opcode);
+                *stats.unknown_opcodes.entry(opcode.clone()).or_default() += 1;
+                self.log
+                    .log(&format!("Ignoring opcode {} from {}", opcode, addr), now);
+            }
+            Decoded::Malformed(e) => {
+                stats.malformed += 1;
+                self.log.log(
+                    &format!("Could not decode packet from {}: {}", addr, e),
+                    now,
+                );
+            }
+        }
+        None
+    }
+
+    pub fn status(&self) -> DecodeStatus {
+        let mut sources: BTreeMap<String, SourceStats> = self
+            .sources
+            .iter()
+            .map(|(ip, stats)| (ip.to_string(), stats.clone()))
+            .collect();
+        if self.sources.len() >= MAX_SOURCES {
+            sources.insert(String::from("other"), self.other.clone());
+        }
+        let sum = |count: fn(&SourceStats) -> u64| sources.values().map(count).sum();
+        DecodeStatus {
+            decoded: sum(|s| s.decoded),
+            unknown_opcode: sum(|s| s.unknown_opcode),
+            malformed: sum(|s| s.malformed),
+            sources,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(buffer: &[u8]) -> Decoded {
+        Codec::default()
+            .decode(&mut BytesMut::from(buffer))
+            .unwrap()
+            .unwrap()
+    }
+
+    fn poll() -> Vec<u8> {
+        Packet::Command(ArtCommand::Poll(Default::default()))
+            .into_buffer()
+            .unwrap()
+    }
+
+    fn unknown_opcode() -> Vec<u8> {
+        let mut buffer = ARTNET_HEADER.to_vec();
+        buffer.extend_from_slice(&0x5200u16.to_le_bytes());
+        buffer.extend_from_slice(&[0, 14, 0, 0]);
+        buffer
+    }
+
+    fn addr(ip: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, ip], 6454))
+    }
+
+    #[test]
+    fn classifies_datagrams() {
+        assert!(matches!(
+            decode(&poll()),
+            Decoded::Packet(Packet::Command(ArtCommand::Poll(_)))
+        ));
+        assert!(matches!(
+            decode(&unknown_opcode()),
+            Decoded::UnknownOpcode(0x5200)
+        ));
+        assert!(matches!(decode(b"not art-net"), Decoded::Malformed(_)));
+        // A known opcode without the rest of the packet
+        let mut truncated = ARTNET_HEADER.to_vec();
+        truncated.extend_from_slice(&0x8100u16.to_le_bytes());
+        truncated.extend_from_slice(&[0, 14]);
+        assert!(matches!(decode(&truncated), Decoded::Malformed(_)));
+    }
+
+    #[test]
+    fn counts_datagrams_per_source() {
+        let mut stats = DecodeStats::default();
+        let unknown = unknown_opcode();
+        assert!(stats.record(addr(1), decode(&poll()), 0.).is_some());
+        assert!(stats.record(addr(1), decode(&unknown), 0.).is_none());
+        assert!(stats.record(addr(1), decode(&unknown), 0.).is_none());
+        assert!(stats.record(addr(2), decode(b"not art-net"), 0.).is_none());
+
+        let status = stats.status();
+        assert_eq!(
+            (status.decoded, status.unknown_opcode, status.malformed),
+            (1, 2, 1)
+        );
+        let first = &status.sources["10.0.0.1"];
+        assert_eq!((first.decoded, first.unknown_opcode), (1, 2));
+        assert_eq!(first.unknown_opcodes["0x5200"], 2);
+        assert_eq!(status.sources["10.0.0.2"].malformed, 1);
+        assert!(!status.sources.contains_key("other"));
+    }
+
+    #[test]
+    fn counts_sources_beyond_the_limit_together() {
+        let mut stats = DecodeStats::default();
+        for i in 0..MAX_SOURCES + 2 {
+            let addr = SocketAddr::from(([10, 0, (i / 256) as u8, i as u8], 6454));
+            stats.record(addr, decode(&poll()), 0.);
+        }
+        let status = stats.status();
+        assert_eq!(status.sources.len(), MAX_SOURCES + 1);
+        assert_eq!(status.sources["other"].decoded, 2);
+        assert_eq!(status.decoded, MAX_SOURCES as u64 + 2);
     }
 }
@@ -0,0 +1,186 @@
+use super::copy_name;
+use crate::config::ArtnetInputConfig;
+use artnet_protocol::PollReply;
+
+/// A high-level function that is triggered by an incoming DMX channel
+#[derive(Debug, PartialEq)]
+pub enum InputAction {
+    MasterBrightness(u8),
+    /// 1-based index into the configured scenes
+    Scene(usize),
+    Color(u8, u8, u8),
+    Blackout(bool),
+    TapTempo,
+}
+
+/// Turns ArtDmx packets from a lighting console into `InputAction`s.
+///
+/// Channels, relative to `start_address`:
+/// 1. master dimmer
+/// 2. scene number, 0 does nothing
+/// 3. - 5. red, green and blue of all torches
+/// 6. blackout, latched above 127
+/// 7. tap tempo, taps when going above 127
+///
+/// Apart from the master dimmer, a channel only does something when its value changes.
+/// This way a console that is just sending its idle state does not override the web API.
+pub struct DmxInput {
+    pub config: ArtnetInputConfig,
+    last: Option<[u8; CHANNELS]>,
+}
+
+const CHANNELS: usize = 7;
+
+impl DmxInput {
+    pub fn new(config: ArtnetInputConfig) -> DmxInput {
+        DmxInput { config, last: None }
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> Vec<InputAction> {
+        let start = usize::from(self.config.start_address.max(1)) - 1;
+        let mut channels = [0u8; CHANNELS];
+        for (channel, value) in channels.iter_mut().zip(data.iter().skip(start)) {
+            *channel = *value;
+        }
+
+        let mut actions = Vec::new();
+        let last = self.last.replace(channels);
+        if last.map(|l| l[0]) != Some(channels[0]) {
+            let percentage = (u16::from(channels[0]) * 100 / 255) as u8;
+            actions.push(InputAction::MasterBrightness(percentage));
+        }
+        let last = match last {
+            Some(last) => last,
+            None => return actions,
+        };
+        if channels[1] != last[1] && channels[1] != 0 {
+            actions.push(InputAction::Scene(usize::from(channels[1])));
+        }
+        if channels[2..5] != last[2..5] {
+            actions.push(InputAction::Color(channels[2], channels[3], channels[4]));
+        }
+        if (channels[5] > 127) != (last[5] > 127) {
+            actions.push(InputAction::Blackout(channels[5] > 127));
+        }
+        if channels[6] > 127 && last[6] <= 127 {
+            actions.push(InputAction::TapTempo);
+        }
+        actions
+    }
+
+    pub fn poll_reply(&self) -> PollReply {
+        let mut short_name = [0u8; 18];
+        let mut long_name = [0u8; 64];
+        copy_name(&mut short_name, &self.config.short_name);
+        copy_name(&mut long_name, &self.config.long_name);
+        let universe = self.config.universe;
+        PollReply {
+            address: self.config.address,
+            port: 0x1936,
+            version: [0, 14],
+            port_address: [(universe >> 8) as u8 & 0x7F, (universe >> 4) as u8 & 0x0F],
+            oem: [0, 0xFF],
+            ubea_version: 0,
+            status_1: 0,
+            esta_code: 0,
+            short_name,
+            long_name,
+            node_report: [0; 64],
+            num_ports: [0, 1],
+            // A single DMX output port, which is how consoles see where to send their universe
+            port_types: [0x80, 0, 0, 0],
+            good_input: [0; 4],
+            good_output: [0x80, 0, 0, 0],
+            swin: [0; 4],
+            swout: [universe as u8 & 0x0F, 0, 0, 0],
+            sw_video: 0,
+            sw_macro: 0,
+            sw_remote: 0,
+            spare: [0; 3],
+            style: 0,
+            mac: [0; 6],
+            bind_ip: self.config.address.octets(),
+            bind_index: 1,
+            status_2: 0b1000,
+            filler: [0; 26],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn input(start_address: u16) -> DmxInput {
+        DmxInput::new(ArtnetInputConfig {
+            address: Ipv4Addr::new(10, 0, 0, 1),
+            universe: 0,
+            start_address,
+            short_name: String::new(),
+            long_name: String::new(),
+        })
+    }
+
+    #[test]
+    fn first_packet_only_sets_the_master() {
+        let mut input = input(1);
+        assert_eq!(
+            input.update(&[255, 3, 10, 20, 30, 255, 255]),
+            [InputAction::MasterBrightness(100)]
+        );
+        // The idle state of a console does nothing
+        assert!(input.update(&[255, 3, 10, 20, 30, 255, 255]).is_empty());
+    }
+
+    #[test]
+    fn acts_on_changed_channels() {
+        let mut input = input(1);
+        input.update(&[0; 7]);
+        assert_eq!(
+            input.update(&[128, 2, 0, 0, 0, 0, 0]),
+            [InputAction::MasterBrightness(50), InputAction::Scene(2)]
+        );
+        // Scene 0 does nothing, but selecting the same scene after it does
+        assert!(input.update(&[128, 0, 0, 0, 0, 0, 0]).is_empty());
+        assert_eq!(
+            input.update(&[128, 2, 0, 0, 0, 0, 0]),
+            [InputAction::Scene(2)]
+        );
+        assert_eq!(
+            input.update(&[128, 2, 0, 255, 0, 0, 0]),
+            [InputAction::Color(0, 255, 0)]
+        );
+    }
+
+    #[test]
+    fn blackout_and_tap_tempo_trigger_on_edges() {
+        let mut input = input(1);
+        input.update(&[0; 7]);
+        assert!(input.update(&[0, 0, 0, 0, 0, 127, 127]).is_empty());
+        assert_eq!(
+            input.update(&[0, 0, 0, 0, 0, 128, 128]),
+            [InputAction::Blackout(true), InputAction::TapTempo]
+        );
+        assert!(input.update(&[0, 0, 0, 0, 0, 255, 255]).is_empty());
+        assert_eq!(
+            input.update(&[0, 0, 0, 0, 0, 0, 0]),
+            [InputAction::Blackout(false)]
+        );
+        assert_eq!(
+            input.update(&[0, 0, 0, 0, 0, 0, 200]),
+            [InputAction::TapTempo]
+        );
+    }
+
+    #[test]
+    fn reads_from_the_start_address() {
+        let mut input = input(3);
+        assert_eq!(
+            input.update(&[1, 2, 51, 0, 0, 0, 0, 0, 0]),
+            [InputAction::MasterBrightness(20)]
+        );
+        // A short packet leaves the missing channels at 0
+        assert_eq!(input.update(&[1, 2, 51, 4]), [InputAction::Scene(4)]);
+    }
+}
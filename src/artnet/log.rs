@@ -0,0 +1,25 @@
+/// Minimum seconds between two logged messages, the ones in between are only counted
+const LOG_INTERVAL: f64 = 5.;
+
+/// Logs at most one message per `LOG_INTERVAL`, for errors that can come in floods
+#[derive(Default)]
+pub struct RateLimitedLog {
+    last_logged: f64,
+    suppressed: u64,
+}
+
+impl RateLimitedLog {
+    pub fn log(&mut self, message: &str, now: f64) {
+        if now - self.last_logged < LOG_INTERVAL {
+            self.suppressed += 1;
+            return;
+        }
+        if self.suppressed > 0 {
+            println!("{} ({} more since the last one)", message, self.suppressed);
+        } else {
+            println!("{}", message);
+        }
+        self.last_logged = now;
+        self.suppressed = 0;
+    }
+}
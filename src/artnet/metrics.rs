@@ -0,0 +1,93 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Polls that the recent round-trip times and missed replies are kept for
+const RECENT_POLLS: usize = 30;
+
+/// Counts what is sent to a node and how it answers ArtPoll, to spot torches with a bad connection
+#[derive(Default)]
+pub struct NodeMetrics {
+    packets_sent: u64,
+    bytes_sent: u64,
+    frames_dropped: u64,
+    polls: u64,
+    missed_replies: u64,
+    /// A poll went out that this node did not reply to yet
+    awaiting_reply: bool,
+    /// The round-trip time of the recent polls, `None` for the ones without a reply
+    recent: VecDeque<Option<f64>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeMetricsStatus {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    /// Frames the socket could not send to this node
+    pub frames_dropped: u64,
+    pub polls: u64,
+    pub missed_poll_replies: u64,
+    /// Seconds from the last ArtPoll to the PollReply of this node
+    pub rtt: Option<f64>,
+    /// The average and maximum round-trip time of the last 30 polls
+    pub rtt_average: Option<f64>,
+    pub rtt_max: Option<f64>,
+    /// Replies missed of the last 30 polls
+    pub recent_missed_replies: usize,
+}
+
+impl NodeMetrics {
+    pub fn sent(&mut self, bytes: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += bytes as u64;
+    }
+
+    pub fn frame_dropped(&mut self) {
+        self.frames_dropped += 1;
+    }
+
+    /// Should be called for every ArtPoll, a previous one without a reply counts as missed
+    pub fn poll_sent(&mut self) {
+        if self.awaiting_reply {
+            self.missed_replies += 1;
+            self.record(None);
+        }
+        self.polls += 1;
+        self.awaiting_reply = true;
+    }
+
+    /// Only the first reply to a poll counts, a node can reply to several broadcasts
+    pub fn reply_received(&mut self, poll_sent: f64, now: f64) {
+        if self.awaiting_reply {
+            self.awaiting_reply = false;
+            self.record(Some(now - poll_sent));
+        }
+    }
+
+    fn record(&mut self, rtt: Option<f64>) {
+        if self.recent.len() >= RECENT_POLLS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(rtt);
+    }
+
+    pub fn status(&self) -> NodeMetricsStatus {
+        let rtts: Vec<f64> = self.recent.iter().flatten().copied().collect();
+        NodeMetricsStatus {
+            packets_sent: self.packets_sent,
+            bytes_sent: self.bytes_sent,
+            frames_dropped: self.frames_dropped,
+            polls: self.polls,
+            missed_poll_replies: self.missed_replies,
+            rtt: self.recent.iter().rev().flatten().next().copied(),
+            rtt_average: if rtts.is_empty() {
+                None
+            } else {
+                Some(rtts.iter().sum::<f64>() / rtts.len() as f64)
+            },
+            rtt_max: rtts.iter().copied().fold(None, |max, rtt| {
+                Some(max.map_or(rtt, |max: f64| max.max(rtt)))
+            }),
+            recent_missed_replies: self.recent.iter().filter(|rtt| rtt.is_none()).count(),
+        }
+    }
+}
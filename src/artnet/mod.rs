@@ -1,5 +1,19 @@
 mod client;
 mod codec;
+mod input;
+mod log;
+mod metrics;
+mod packet;
+pub mod rdm;
+mod socket;
 
-pub use self::client::{Client, RenderMode};
-pub use self::codec::Codec;
+pub use self::client::{ChangeStatus, Client, PendingChange, RenderMode};
+pub use self::codec::{Codec, DecodeStats, DecodeStatus, Decoded};
+pub use self::input::{DmxInput, InputAction};
+pub use self::metrics::{NodeMetrics, NodeMetricsStatus};
+pub use self::packet::{
+    copy_name, read_name, Address, AddressCommand, Dmx, FirmwareBlock, FirmwareMaster,
+    FirmwareReply, IpProg, IpProgReply, Packet, Rdm, TimeCode, TodData, TodRequest, Trigger,
+    FIRMWARE_BLOCK_SIZE,
+};
+pub use self::socket::{bind, LinkState, Transport, TransportStatus};
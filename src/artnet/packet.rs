@@ -0,0 +1,619 @@
+use super::rdm::{RdmMessage, Uid};
+use crate::Result;
+use artnet_protocol::ArtCommand;
+use bytes::Bytes;
+use std::net::Ipv4Addr;
+use std::str;
+
+const ARTNET_HEADER: &[u8] = b"Art-Net\0";
+const PROTOCOL_VERSION: [u8; 2] = [0, 14];
+const OP_DMX: u16 = 0x5000;
+const OP_ADDRESS: u16 = 0x6000;
+const OP_TIME_CODE: u16 = 0x9700;
+const OP_TRIGGER: u16 = 0x9900;
+const OP_IP_PROG: u16 = 0xF800;
+const OP_IP_PROG_REPLY: u16 = 0xF900;
+const OP_TOD_REQUEST: u16 = 0x8000;
+const OP_TOD_DATA: u16 = 0x8100;
+const OP_RDM: u16 = 0x8300;
+const RDM_VERSION: u8 = 0x01;
+const OP_FIRMWARE_MASTER: u16 = 0xF200;
+const OP_FIRMWARE_REPLY: u16 = 0xF300;
+/// The amount of firmware bytes in an ArtFirmwareMaster packet
+pub const FIRMWARE_BLOCK_SIZE: usize = 1024;
+/// Bit 7 marks a switch value in ArtAddress as one to program
+const PROGRAM: u8 = 0x80;
+/// A switch value in ArtAddress that leaves the setting unchanged
+const NO_CHANGE: u8 = 0x7F;
+
+/// A received packet, either one that `artnet_protocol` can decode or one that it only knows the
+/// opcode of
+#[derive(Debug)]
+pub enum Packet {
+    Command(ArtCommand),
+    /// Only sent, received ArtDmx packets are decoded as `ArtCommand::Output`
+    Dmx(Dmx),
+    Trigger(Trigger),
+    TimeCode(TimeCode),
+    Address(Address),
+    IpProg(IpProg),
+    IpProgReply(IpProgReply),
+    FirmwareMaster(FirmwareMaster),
+    FirmwareReply(FirmwareReply),
+    TodRequest(TodRequest),
+    TodData(TodData),
+    Rdm(Rdm),
+}
+
+impl From<ArtCommand> for Packet {
+    fn from(command: ArtCommand) -> Packet {
+        Packet::Command(command)
+    }
+}
+
+/// An ArtDmx packet with a payload that is shared instead of copied, see `output::encode`
+#[derive(Debug, Clone)]
+pub struct Dmx {
+    /// The 15-bit Port-Address
+    pub universe: u16,
+    pub data: Bytes,
+}
+
+/// An ArtTrigger packet
+#[derive(Debug)]
+pub struct Trigger {
+    /// 0xFFFF for the standard keys, otherwise the manufacturer that defines `key`
+    pub oem: u16,
+    pub key: u8,
+    pub sub_key: u8,
+}
+
+/// An ArtTimeCode packet
+#[derive(Debug, Clone, Copy)]
+pub struct TimeCode {
+    pub frames: u8,
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    /// 0 is Film (24 fps), 1 EBU (25 fps), 2 DF (29.97 fps) and 3 SMPTE (30 fps)
+    pub kind: u8,
+}
+
+/// An ArtAddress packet, every field that is `None` is left unchanged on the node
+#[derive(Debug, Default)]
+pub struct Address {
+    pub short_name: Option<String>,
+    pub long_name: Option<String>,
+    /// The 15-bit Port-Address of the first output port
+    pub universe: Option<u16>,
+    pub command: AddressCommand,
+}
+
+/// The commands of ArtAddress that apply to the first port of a node
+#[derive(Debug, Clone, Copy, Default)]
+pub enum AddressCommand {
+    #[default]
+    None,
+    CancelMerge,
+    LedNormal,
+    LedMute,
+    LedLocate,
+    MergeLtp,
+    MergeHtp,
+    ClearOutput,
+}
+
+impl AddressCommand {
+    pub fn parse(command: &str) -> Result<AddressCommand> {
+        Ok(match command {
+            "none" => AddressCommand::None,
+            "cancel_merge" => AddressCommand::CancelMerge,
+            "led_normal" => AddressCommand::LedNormal,
+            "led_mute" => AddressCommand::LedMute,
+            "led_locate" => AddressCommand::LedLocate,
+            "merge_ltp" => AddressCommand::MergeLtp,
+            "merge_htp" => AddressCommand::MergeHtp,
+            "clear_output" => AddressCommand::ClearOutput,
+            _ => bail!("Unknown command {:?}", command),
+        })
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            AddressCommand::None => 0x00,
+            AddressCommand::CancelMerge => 0x01,
+            AddressCommand::LedNormal => 0x02,
+            AddressCommand::LedMute => 0x03,
+            AddressCommand::LedLocate => 0x04,
+            AddressCommand::MergeLtp => 0x10,
+            AddressCommand::MergeHtp => 0x50,
+            AddressCommand::ClearOutput => 0x90,
+        }
+    }
+}
+
+/// An ArtIpProg packet
+#[derive(Debug)]
+pub enum IpProg {
+    /// Only asks for the current settings
+    Query,
+    Dhcp,
+    Static {
+        address: Ipv4Addr,
+        mask: Ipv4Addr,
+    },
+}
+
+/// An ArtIpProgReply packet, the network settings of a node after an ArtIpProg
+#[derive(Debug)]
+pub struct IpProgReply {
+    pub address: Ipv4Addr,
+    pub mask: Ipv4Addr,
+    pub dhcp: bool,
+}
+
+/// An ArtFirmwareMaster packet, one block of a firmware upload
+#[derive(Debug)]
+pub struct FirmwareMaster {
+    pub block: FirmwareBlock,
+    /// Counts the blocks of an upload, wrapping around after 255
+    pub block_id: u8,
+    /// The length of the whole firmware in 16-bit words
+    pub firmware_length: u32,
+    /// At most `FIRMWARE_BLOCK_SIZE` bytes, padded with zeroes when sent
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FirmwareBlock {
+    First,
+    Continue,
+    Last,
+}
+
+/// An ArtFirmwareReply packet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FirmwareReply {
+    /// The last block was received
+    BlockGood,
+    /// The whole firmware was received and is being installed
+    AllGood,
+    Fail,
+}
+
+/// An ArtTodRequest packet, asks for the RDM devices on the given universes
+#[derive(Debug)]
+pub struct TodRequest {
+    /// Bits 14-8 of the Port-Addresses
+    pub net: u8,
+    /// The low bytes of the Port-Addresses
+    pub addresses: Vec<u8>,
+}
+
+/// An ArtTodData packet, (part of) the RDM devices on a universe
+#[derive(Debug)]
+pub struct TodData {
+    pub port: u8,
+    pub net: u8,
+    pub address: u8,
+    /// The amount of devices on the universe, over all ArtTodData packets
+    pub uid_total: u16,
+    pub block_count: u8,
+    pub uids: Vec<Uid>,
+}
+
+/// An ArtRdm packet
+#[derive(Debug)]
+pub struct Rdm {
+    pub net: u8,
+    pub address: u8,
+    pub message: RdmMessage,
+}
+
+impl Packet {
+    pub fn into_buffer(self) -> Result<Vec<u8>> {
+        let (opcode, data) = match self {
+            Packet::Command(command) => return Ok(command.into_buffer()?),
+            // The same bytes as an `ArtCommand::Output`, which is what the torches expect
+            Packet::Dmx(dmx) => {
+                let mut buffer = Vec::with_capacity(18 + dmx.data.len());
+                buffer.extend_from_slice(ARTNET_HEADER);
+                buffer.extend_from_slice(&OP_DMX.to_le_bytes());
+                buffer.extend_from_slice(&artnet_protocol::ARTNET_PROTOCOL_VERSION);
+                // No sequence and physical port
+                buffer.extend_from_slice(&[0, 0]);
+                buffer.extend_from_slice(&dmx.universe.to_le_bytes());
+                buffer.extend_from_slice(&(dmx.data.len() as u16).to_le_bytes());
+                buffer.extend_from_slice(&dmx.data);
+                return Ok(buffer);
+            }
+            // The offsets below are relative to the end of the protocol version, at byte 12
+            Packet::Trigger(trigger) => {
+                let mut data = vec![0; 518];
+                data[2..4].copy_from_slice(&trigger.oem.to_be_bytes());
+                data[4] = trigger.key;
+                data[5] = trigger.sub_key;
+                (OP_TRIGGER, data)
+            }
+            Packet::TimeCode(timecode) => (
+                OP_TIME_CODE,
+                vec![
+                    0,
+                    0,
+                    timecode.frames,
+                    timecode.seconds,
+                    timecode.minutes,
+                    timecode.hours,
+                    timecode.kind,
+                ],
+            ),
+            Packet::Address(address) => {
+                let mut data = vec![0; 95];
+                data[0] = address
+                    .universe
+                    .map_or(NO_CHANGE, |u| PROGRAM | (u >> 8) as u8 & 0x7F);
+                // An empty name means no change
+                if let Some(name) = &address.short_name {
+                    copy_name(&mut data[2..20], name);
+                }
+                if let Some(name) = &address.long_name {
+                    copy_name(&mut data[20..84], name);
+                }
+                for switch in &mut data[84..93] {
+                    *switch = NO_CHANGE;
+                }
+                if let Some(universe) = address.universe {
+                    data[88] = PROGRAM | universe as u8 & 0x0F;
+                    data[92] = PROGRAM | (universe >> 4) as u8 & 0x0F;
+                }
+                data[93] = 0;
+                data[94] = address.command.code();
+                (OP_ADDRESS, data)
+            }
+            Packet::IpProg(prog) => {
+                let mut data = vec![0; 22];
+                match prog {
+                    IpProg::Query => {}
+                    // Enable programming and DHCP
+                    IpProg::Dhcp => data[2] = 0x80 | 0x40,
+                    IpProg::Static { address, mask } => {
+                        // Enable programming of the IP address and subnet mask
+                        data[2] = 0x80 | 0x04 | 0x02;
+                        data[4..8].copy_from_slice(&address.octets());
+                        data[8..12].copy_from_slice(&mask.octets());
+                    }
+                }
+                (OP_IP_PROG, data)
+            }
+            Packet::IpProgReply(reply) => {
+                let mut data = vec![0; 22];
+                data[4..8].copy_from_slice(&reply.address.octets());
+                data[8..12].copy_from_slice(&reply.mask.octets());
+                data[14] = if reply.dhcp { 0x40 } else { 0 };
+                (OP_IP_PROG_REPLY, data)
+            }
+            Packet::FirmwareMaster(master) => {
+                let mut data = vec![0; 28 + FIRMWARE_BLOCK_SIZE];
+                data[2] = match master.block {
+                    FirmwareBlock::First => 0x00,
+                    FirmwareBlock::Continue => 0x01,
+                    FirmwareBlock::Last => 0x02,
+                };
+                data[3] = master.block_id;
+                data[4..8].copy_from_slice(&master.firmware_length.to_be_bytes());
+                let len = master.data.len().min(FIRMWARE_BLOCK_SIZE);
+                data[28..28 + len].copy_from_slice(&master.data[..len]);
+                (OP_FIRMWARE_MASTER, data)
+            }
+            Packet::FirmwareReply(reply) => {
+                let mut data = vec![0; 24];
+                data[2] = match reply {
+                    FirmwareReply::BlockGood => 0x00,
+                    FirmwareReply::AllGood => 0x01,
+                    FirmwareReply::Fail => 0xFF,
+                };
+                (OP_FIRMWARE_REPLY, data)
+            }
+            Packet::TodRequest(request) => {
+                let mut data = vec![0; 12];
+                data[9] = request.net;
+                data[11] = request.addresses.len() as u8;
+                data.extend_from_slice(&request.addresses);
+                (OP_TOD_REQUEST, data)
+            }
+            Packet::TodData(tod) => {
+                let mut data = vec![0; 16];
+                data[0] = RDM_VERSION;
+                data[1] = tod.port;
+                data[9] = tod.net;
+                data[11] = tod.address;
+                data[12..14].copy_from_slice(&tod.uid_total.to_be_bytes());
+                data[14] = tod.block_count;
+                data[15] = tod.uids.len() as u8;
+                for uid in &tod.uids {
+                    data.extend_from_slice(&uid.0);
+                }
+                (OP_TOD_DATA, data)
+            }
+            Packet::Rdm(rdm) => {
+                let mut data = vec![0; 12];
+                data[0] = RDM_VERSION;
+                data[9] = rdm.net;
+                data[11] = rdm.address;
+                data.extend_from_slice(&rdm.message.encode()?);
+                (OP_RDM, data)
+            }
+        };
+        let mut buffer = Vec::with_capacity(12 + data.len());
+        buffer.extend_from_slice(ARTNET_HEADER);
+        buffer.extend_from_slice(&opcode.to_le_bytes());
+        buffer.extend_from_slice(&PROTOCOL_VERSION);
+        buffer.extend_from_slice(&data);
+        Ok(buffer)
+    }
+
+    pub fn from_buffer(buffer: &[u8]) -> Result<Packet> {
+        if buffer.len() < 10 || &buffer[..8] != ARTNET_HEADER {
+            return Ok(Packet::Command(ArtCommand::from_buffer(buffer)?));
+        }
+        let opcode = u16::from_le_bytes([buffer[8], buffer[9]]);
+        Ok(match opcode {
+            OP_TRIGGER => {
+                if buffer.len() < 18 {
+                    bail!("ArtTrigger too short: {} bytes", buffer.len());
+                }
+                Packet::Trigger(Trigger {
+                    oem: u16::from_be_bytes([buffer[14], buffer[15]]),
+                    key: buffer[16],
+                    sub_key: buffer[17],
+                })
+            }
+            OP_TIME_CODE => {
+                if buffer.len() < 19 {
+                    bail!("ArtTimeCode too short: {} bytes", buffer.len());
+                }
+                Packet::TimeCode(TimeCode {
+                    frames: buffer[14],
+                    seconds: buffer[15],
+                    minutes: buffer[16],
+                    hours: buffer[17],
+                    kind: buffer[18],
+                })
+            }
+            OP_ADDRESS => {
+                if buffer.len() < 107 {
+                    bail!("ArtAddress too short: {} bytes", buffer.len());
+                }
+                let programmed = |switch: u8| switch & PROGRAM != 0;
+                let (net, sub, port) = (buffer[12], buffer[104], buffer[100]);
+                let universe = if programmed(net) && programmed(sub) && programmed(port) {
+                    Some(
+                        u16::from(net & 0x7F) << 8
+                            | u16::from(sub & 0x0F) << 4
+                            | u16::from(port & 0x0F),
+                    )
+                } else {
+                    None
+                };
+                Packet::Address(Address {
+                    short_name: Some(read_name(&buffer[14..32])?).filter(|n| !n.is_empty()),
+                    long_name: Some(read_name(&buffer[32..96])?).filter(|n| !n.is_empty()),
+                    universe,
+                    command: match buffer[106] {
+                        0x01 => AddressCommand::CancelMerge,
+                        0x02 => AddressCommand::LedNormal,
+                        0x03 => AddressCommand::LedMute,
+                        0x04 => AddressCommand::LedLocate,
+                        0x10 => AddressCommand::MergeLtp,
+                        0x50 => AddressCommand::MergeHtp,
+                        0x90 => AddressCommand::ClearOutput,
+                        _ => AddressCommand::None,
+                    },
+                })
+            }
+            OP_IP_PROG => {
+                if buffer.len() < 24 {
+                    bail!("ArtIpProg too short: {} bytes", buffer.len());
+                }
+                let command = buffer[14];
+                if command & 0x80 == 0 {
+                    Packet::IpProg(IpProg::Query)
+                } else if command & 0x40 != 0 {
+                    Packet::IpProg(IpProg::Dhcp)
+                } else {
+                    Packet::IpProg(IpProg::Static {
+                        address: read_ip(&buffer[16..20]),
+                        mask: read_ip(&buffer[20..24]),
+                    })
+                }
+            }
+            OP_IP_PROG_REPLY => {
+                if buffer.len() < 27 {
+                    bail!("ArtIpProgReply too short: {} bytes", buffer.len());
+                }
+                Packet::IpProgReply(IpProgReply {
+                    address: read_ip(&buffer[16..20]),
+                    mask: read_ip(&buffer[20..24]),
+                    dhcp: buffer[26] & 0x40 != 0,
+                })
+            }
+            OP_FIRMWARE_MASTER => {
+                if buffer.len() < 40 + FIRMWARE_BLOCK_SIZE {
+                    bail!("ArtFirmwareMaster too short: {} bytes", buffer.len());
+                }
+                Packet::FirmwareMaster(FirmwareMaster {
+                    block: match buffer[14] {
+                        0x00 | 0x03 => FirmwareBlock::First,
+                        0x02 | 0x05 => FirmwareBlock::Last,
+                        _ => FirmwareBlock::Continue,
+                    },
+                    block_id: buffer[15],
+                    firmware_length: u32::from_be_bytes([
+                        buffer[16], buffer[17], buffer[18], buffer[19],
+                    ]),
+                    data: buffer[40..40 + FIRMWARE_BLOCK_SIZE].to_vec(),
+                })
+            }
+            OP_FIRMWARE_REPLY => {
+                if buffer.len() < 15 {
+                    bail!("ArtFirmwareReply too short: {} bytes", buffer.len());
+                }
+                Packet::FirmwareReply(match buffer[14] {
+                    0x00 => FirmwareReply::BlockGood,
+                    0x01 => FirmwareReply::AllGood,
+                    _ => FirmwareReply::Fail,
+                })
+            }
+            OP_TOD_REQUEST => {
+                if buffer.len() < 24 {
+                    bail!("ArtTodRequest too short: {} bytes", buffer.len());
+                }
+                let count = usize::from(buffer[23]).min(buffer.len() - 24);
+                Packet::TodRequest(TodRequest {
+                    net: buffer[21],
+                    addresses: buffer[24..24 + count].to_vec(),
+                })
+            }
+            OP_TOD_DATA => {
+                if buffer.len() < 28 {
+                    bail!("ArtTodData too short: {} bytes", buffer.len());
+                }
+                let count = usize::from(buffer[27]).min((buffer.len() - 28) / 6);
+                Packet::TodData(TodData {
+                    port: buffer[13],
+                    net: buffer[21],
+                    address: buffer[23],
+                    uid_total: u16::from_be_bytes([buffer[24], buffer[25]]),
+                    block_count: buffer[26],
+                    uids: buffer[28..28 + count * 6]
+                        .chunks_exact(6)
+                        .map(|b| Uid([b[0], b[1], b[2], b[3], b[4], b[5]]))
+                        .collect(),
+                })
+            }
+            OP_RDM => {
+                if buffer.len() < 24 {
+                    bail!("ArtRdm too short: {} bytes", buffer.len());
+                }
+                Packet::Rdm(Rdm {
+                    net: buffer[21],
+                    address: buffer[23],
+                    message: RdmMessage::decode(&buffer[24..])?,
+                })
+            }
+            _ => Packet::Command(ArtCommand::from_buffer(buffer)?),
+        })
+    }
+}
+
+impl TimeCode {
+    pub fn fps(&self) -> f64 {
+        match self.kind {
+            0 => 24.,
+            1 => 25.,
+            2 => 29.97,
+            _ => 30.,
+        }
+    }
+
+    /// The position in seconds
+    pub fn position(&self) -> f64 {
+        f64::from(self.hours) * 3600.
+            + f64::from(self.minutes) * 60.
+            + f64::from(self.seconds)
+            + f64::from(self.frames) / self.fps()
+    }
+}
+
+impl std::fmt::Display for TimeCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+/// Copies a name into a null terminated field, cut off so the terminator fits
+pub fn copy_name(field: &mut [u8], name: &str) {
+    let len = name.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&name.as_bytes()[..len]);
+}
+
+/// Reads a null padded name
+pub fn read_name(field: &[u8]) -> Result<String> {
+    let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    Ok(str::from_utf8(&field[..len])?.to_owned())
+}
+
+fn read_ip(field: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(field[0], field[1], field[2], field[3])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uid(device: u8) -> Uid {
+        Uid([0x7f, 0xf1, 0, 0, 0, device])
+    }
+
+    #[test]
+    fn tod_data_round_trip() {
+        let buffer = Packet::TodData(TodData {
+            port: 1,
+            net: 2,
+            address: 3,
+            uid_total: 5,
+            block_count: 1,
+            uids: vec![uid(1), uid(2)],
+        })
+        .into_buffer()
+        .unwrap();
+        match Packet::from_buffer(&buffer).unwrap() {
+            Packet::TodData(tod) => {
+                assert_eq!((tod.port, tod.net, tod.address), (1, 2, 3));
+                assert_eq!((tod.uid_total, tod.block_count), (5, 1));
+                assert_eq!(tod.uids, [uid(1), uid(2)]);
+            }
+            packet => panic!("Expected ArtTodData, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn tod_data_ignores_missing_uids() {
+        let mut buffer = Packet::TodData(TodData {
+            port: 1,
+            net: 0,
+            address: 0,
+            uid_total: 3,
+            block_count: 0,
+            uids: vec![uid(1), uid(2)],
+        })
+        .into_buffer()
+        .unwrap();
+        // Claims three UIDs, and the last one is cut off
+        buffer[27] = 3;
+        buffer.truncate(buffer.len() - 2);
+        match Packet::from_buffer(&buffer).unwrap() {
+            Packet::TodData(tod) => assert_eq!(tod.uids, [uid(1)]),
+            packet => panic!("Expected ArtTodData, got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn tod_data_rejects_short_packet() {
+        let buffer = Packet::TodData(TodData {
+            port: 1,
+            net: 0,
+            address: 0,
+            uid_total: 0,
+            block_count: 0,
+            uids: Vec::new(),
+        })
+        .into_buffer()
+        .unwrap();
+        assert!(Packet::from_buffer(&buffer[..27]).is_err());
+    }
+}
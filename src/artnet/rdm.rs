@@ -0,0 +1,170 @@
+use crate::Result;
+use std::fmt;
+
+/// The DMX start code of RDM, not part of the RDM data in ArtRdm but part of the checksum
+const START_CODE: u8 = 0xCC;
+const SUB_START_CODE: u8 = 0x01;
+/// The message length is a byte and includes the 24 bytes before the data
+const MAX_DATA_LENGTH: usize = 231;
+
+pub const GET_COMMAND: u8 = 0x20;
+pub const GET_COMMAND_RESPONSE: u8 = 0x21;
+
+pub const RESPONSE_ACK: u8 = 0x00;
+
+pub const PID_SENSOR_DEFINITION: u16 = 0x0200;
+pub const PID_SENSOR_VALUE: u16 = 0x0201;
+pub const PID_DEVICE_INFO: u16 = 0x0060;
+pub const PID_DEVICE_MODEL_DESCRIPTION: u16 = 0x0080;
+pub const PID_DEVICE_LABEL: u16 = 0x0082;
+pub const PID_LAMP_HOURS: u16 = 0x0401;
+
+/// A 48-bit RDM unique ID, a 16-bit manufacturer ID followed by a 32-bit device ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uid(pub [u8; 6]);
+
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = self.0;
+        write!(
+            f,
+            "{:02x}{:02x}:{:02x}{:02x}{:02x}{:02x}",
+            b[0], b[1], b[2], b[3], b[4], b[5]
+        )
+    }
+}
+
+/// An RDM message as carried in ArtRdm, without the start code
+#[derive(Debug, Clone, PartialEq)]
+pub struct RdmMessage {
+    pub destination: Uid,
+    pub source: Uid,
+    pub transaction: u8,
+    /// The port ID in requests, the response type in responses
+    pub port_or_response: u8,
+    pub sub_device: u16,
+    pub command_class: u8,
+    pub pid: u16,
+    pub data: Vec<u8>,
+}
+
+impl RdmMessage {
+    pub fn get(source: Uid, destination: Uid, transaction: u8, pid: u16, data: Vec<u8>) -> Self {
+        RdmMessage {
+            destination,
+            source,
+            transaction,
+            port_or_response: 1,
+            sub_device: 0,
+            command_class: GET_COMMAND,
+            pid,
+            data,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        if self.data.len() > MAX_DATA_LENGTH {
+            bail!(
+                "RDM data too long: {} bytes, at most {}",
+                self.data.len(),
+                MAX_DATA_LENGTH
+            );
+        }
+        let mut buffer = Vec::with_capacity(26 + self.data.len());
+        buffer.push(SUB_START_CODE);
+        // The length includes the start code but not the checksum
+        buffer.push(24 + self.data.len() as u8);
+        buffer.extend_from_slice(&self.destination.0);
+        buffer.extend_from_slice(&self.source.0);
+        buffer.push(self.transaction);
+        buffer.push(self.port_or_response);
+        buffer.push(0);
+        buffer.extend_from_slice(&self.sub_device.to_be_bytes());
+        buffer.push(self.command_class);
+        buffer.extend_from_slice(&self.pid.to_be_bytes());
+        buffer.push(self.data.len() as u8);
+        buffer.extend_from_slice(&self.data);
+        let checksum = checksum(&buffer);
+        buffer.extend_from_slice(&checksum.to_be_bytes());
+        Ok(buffer)
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<RdmMessage> {
+        if buffer.len() < 25 || buffer[0] != SUB_START_CODE {
+            bail!("Not an RDM message");
+        }
+        let data_length = usize::from(buffer[22]);
+        let end = 23 + data_length;
+        if buffer.len() < end + 2 {
+            bail!("RDM message too short: {} bytes", buffer.len());
+        }
+        if checksum(&buffer[..end]) != u16::from_be_bytes([buffer[end], buffer[end + 1]]) {
+            bail!("RDM checksum invalid");
+        }
+        let uid = |b: &[u8]| Uid([b[0], b[1], b[2], b[3], b[4], b[5]]);
+        Ok(RdmMessage {
+            destination: uid(&buffer[2..8]),
+            source: uid(&buffer[8..14]),
+            transaction: buffer[14],
+            port_or_response: buffer[15],
+            sub_device: u16::from_be_bytes([buffer[17], buffer[18]]),
+            command_class: buffer[19],
+            pid: u16::from_be_bytes([buffer[20], buffer[21]]),
+            data: buffer[23..end].to_vec(),
+        })
+    }
+}
+
+fn checksum(buffer: &[u8]) -> u16 {
+    buffer.iter().fold(u16::from(START_CODE), |sum, b| {
+        sum.wrapping_add(u16::from(*b))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(data: Vec<u8>) -> RdmMessage {
+        RdmMessage::get(
+            Uid([0x7f, 0xf0, 0, 0, 0, 1]),
+            Uid([0x7f, 0xf1, 0, 0, 0, 2]),
+            7,
+            PID_DEVICE_LABEL,
+            data,
+        )
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let message = message(vec![1, 2, 3]);
+        let buffer = message.encode().unwrap();
+        // The length counts the start code, which is not in the buffer, but not the checksum
+        assert_eq!(buffer[1], 24 + 3);
+        assert_eq!(buffer.len(), 23 + 3 + 2);
+        assert_eq!(RdmMessage::decode(&buffer).unwrap(), message);
+    }
+
+    #[test]
+    fn decode_rejects_invalid_checksum() {
+        let mut buffer = message(vec![1, 2, 3]).encode().unwrap();
+        buffer[23] ^= 0xFF;
+        assert!(RdmMessage::decode(&buffer).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_truncated_message() {
+        let buffer = message(vec![1, 2, 3]).encode().unwrap();
+        assert!(RdmMessage::decode(&buffer[..buffer.len() - 1]).is_err());
+        assert!(RdmMessage::decode(&buffer[..20]).is_err());
+    }
+
+    #[test]
+    fn encode_rejects_too_much_data() {
+        let longest = message(vec![0; MAX_DATA_LENGTH]);
+        let buffer = longest.encode().unwrap();
+        assert_eq!(buffer[1], 255);
+        assert_eq!(RdmMessage::decode(&buffer).unwrap(), longest);
+        assert!(message(vec![0; MAX_DATA_LENGTH + 1]).encode().is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use super::log::RateLimitedLog;
+use crate::Result;
+use net2::{UdpBuilder, UdpSocketExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+
+/// Seconds of failing sends after which the socket is rebuilt
+const REBUILD_AFTER: f64 = 5.;
+/// Seconds after the last error before the state is back to `Ok`
+const RECOVERY_TIME: f64 = 10.;
+/// Packets that wait for another attempt, the oldest is dropped when it is full
+const RETRY_QUEUE_SIZE: usize = 64;
+/// Attempts of a packet before it is dropped
+const MAX_ATTEMPTS: u8 = 3;
+/// Room for the frames of a tick to a few hundred torches
+const SEND_BUFFER_SIZE: usize = 1024 * 1024;
+/// The most packets a single `sendmmsg` sends
+#[cfg(target_os = "linux")]
+const MAX_BATCH: usize = 1024;
+
+/// Binds the Art-Net port. SO_REUSEPORT lets other Art-Net software on this machine bind it too.
+pub fn bind() -> Result<UdpSocket> {
+    let builder = UdpBuilder::new_v4()?;
+    #[cfg(unix)]
+    net2::unix::UnixUdpBuilderExt::reuse_port(&builder, true)?;
+    let socket = builder.bind("0.0.0.0:6454")?;
+    socket.set_broadcast(true)?;
+    // The kernel may give less, which only means more dropped frames on a busy network
+    if let Err(e) = socket.set_send_buffer_size(SEND_BUFFER_SIZE) {
+        println!("Could not grow the send buffer: {}", e);
+    }
+    Ok(socket)
+}
+
+/// Sends encoded packets, and keeps track of the errors instead of giving up on the first one.
+///
+/// A cable that is unplugged makes every send fail until it is back. Packets that are not sent
+/// again anyway, unlike frames and polls, are retried on the next tick. When sending keeps
+/// failing the socket is rebuilt.
+#[derive(Default)]
+pub struct Transport {
+    socket: Option<UdpSocket>,
+    retry: VecDeque<(Vec<u8>, SocketAddr, u8)>,
+    sent: u64,
+    send_errors: u64,
+    dropped: u64,
+    receive_errors: u64,
+    rebuilds: u64,
+    /// Start of the current run of failing sends
+    failing_since: Option<f64>,
+    last_error: Option<(String, f64)>,
+    log: RateLimitedLog,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum LinkState {
+    Ok,
+    /// Errors happened recently, but packets still go out
+    Degraded,
+    /// There is no socket, it is rebuilt every tick
+    Down,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransportStatus {
+    pub state: LinkState,
+    pub sent: u64,
+    pub send_errors: u64,
+    /// Packets given up on, because the send buffer was full or they failed too often
+    pub dropped: u64,
+    pub receive_errors: u64,
+    pub rebuilds: u64,
+    pub retry_queue: usize,
+    pub failing_for: Option<f64>,
+    pub last_error: Option<String>,
+    pub last_error_age: Option<f64>,
+}
+
+impl Transport {
+    /// Sends a packet, `retry` queues it for another attempt if that fails
+    pub fn send(&mut self, buffer: Vec<u8>, addr: SocketAddr, retry: bool, now: f64) {
+        self.attempt(buffer, addr, if retry { 1 } else { MAX_ATTEMPTS }, now);
+    }
+
+    /// Sends packets that are not retried, with as few system calls as possible.
+    /// Returns the addresses of the packets that were dropped.
+    pub fn send_batch(&mut self, packets: &[(Vec<u8>, SocketAddr)], now: f64) -> Vec<SocketAddr> {
+        let mut dropped = Vec::new();
+        let mut start = 0;
+        while start < packets.len() {
+            let result = match &self.socket {
+                Some(socket) => send_many(socket, &packets[start..]),
+                None => {
+                    dropped.extend(packets[start..].iter().map(|(_, addr)| *addr));
+                    break;
+                }
+            };
+            match result {
+                Ok(sent) => {
+                    self.sent += sent as u64;
+                    self.failing_since = None;
+                    start += sent;
+                }
+                // Waiting for room would delay the next tick, the rest is stale by then anyway
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    dropped.extend(packets[start..].iter().map(|(_, addr)| *addr));
+                    self.error(String::from("Send buffer full, dropping packets"), now);
+                    break;
+                }
+                // The first packet failed, the ones after it may still go out
+                Err(e) => {
+                    let addr = packets[start].1;
+                    self.send_errors += 1;
+                    self.failing_since.get_or_insert(now);
+                    self.error(format!("Could not send to {}: {}", addr, e), now);
+                    dropped.push(addr);
+                    start += 1;
+                }
+            }
+        }
+        self.dropped += dropped.len() as u64;
+        dropped
+    }
+
+    /// Sends the queued packets again
+    pub fn tick(&mut self, now: f64) {
+        for (buffer, addr, attempts) in self.retry.split_off(0) {
+            self.attempt(buffer, addr, attempts + 1, now);
+        }
+    }
+
+    fn attempt(&mut self, buffer: Vec<u8>, addr: SocketAddr, attempts: u8, now: f64) {
+        let result = match &self.socket {
+            Some(socket) => socket.send_to(&buffer, addr),
+            None => {
+                self.give_up(buffer, addr, attempts);
+                return;
+            }
+        };
+        match result {
+            Ok(_) => {
+                self.sent += 1;
+                self.failing_since = None;
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                self.dropped += 1;
+                self.error(String::from("Send buffer full, dropping packets"), now);
+            }
+            Err(e) => {
+                self.send_errors += 1;
+                self.failing_since.get_or_insert(now);
+                self.error(format!("Could not send to {}: {}", addr, e), now);
+                self.give_up(buffer, addr, attempts);
+            }
+        }
+    }
+
+    fn give_up(&mut self, buffer: Vec<u8>, addr: SocketAddr, attempts: u8) {
+        if attempts >= MAX_ATTEMPTS {
+            self.dropped += 1;
+            return;
+        }
+        if self.retry.len() >= RETRY_QUEUE_SIZE {
+            self.retry.pop_front();
+            self.dropped += 1;
+        }
+        self.retry.push_back((buffer, addr, attempts));
+    }
+
+    pub fn receive_failed(&mut self, error: &failure::Error, now: f64) {
+        self.receive_errors += 1;
+        self.error(format!("Could not receive: {}", error), now);
+    }
+
+    fn error(&mut self, message: String, now: f64) {
+        self.log.log(&message, now);
+        self.last_error = Some((message, now));
+    }
+
+    pub fn needs_rebuild(&self, now: f64) -> bool {
+        match self.failing_since {
+            _ if self.socket.is_none() => true,
+            Some(since) => now - since >= REBUILD_AFTER,
+            None => false,
+        }
+    }
+
+    /// Sends over a new socket from now on
+    pub fn replace(&mut self, socket: UdpSocket) {
+        if self.socket.is_some() {
+            println!("Rebuilt the Art-Net socket after failing sends");
+            self.rebuilds += 1;
+        } else if self.last_error.is_some() {
+            println!("Art-Net socket is back up");
+        }
+        self.socket = Some(socket);
+        self.failing_since = None;
+    }
+
+    /// Drops the socket, after it stopped or binding a new one failed
+    pub fn set_down(&mut self, error: &failure::Error, now: f64) {
+        self.socket = None;
+        self.error(format!("Art-Net socket down: {}", error), now);
+    }
+
+    pub fn status(&self, now: f64) -> TransportStatus {
+        let recent_error = match &self.last_error {
+            Some((_, at)) => now - at < RECOVERY_TIME,
+            None => false,
+        };
+        TransportStatus {
+            state: if self.socket.is_none() {
+                LinkState::Down
+            } else if recent_error || self.failing_since.is_some() {
+                LinkState::Degraded
+            } else {
+                LinkState::Ok
+            },
+            sent: self.sent,
+            send_errors: self.send_errors,
+            dropped: self.dropped,
+            receive_errors: self.receive_errors,
+            rebuilds: self.rebuilds,
+            retry_queue: self.retry.len(),
+            failing_for: self.failing_since.map(|since| now - since),
+            last_error: self.last_error.as_ref().map(|(e, _)| e.clone()),
+            last_error_age: self.last_error.as_ref().map(|(_, at)| now - at),
+        }
+    }
+}
+
+/// Sends packets from the start of `packets` with `sendmmsg`, returns how many were sent
+#[cfg(target_os = "linux")]
+fn send_many(socket: &UdpSocket, packets: &[(Vec<u8>, SocketAddr)]) -> io::Result<usize> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    let packets = &packets[..packets.len().min(MAX_BATCH)];
+    let mut addrs: Vec<(libc::sockaddr_storage, libc::socklen_t)> =
+        packets.iter().map(|(_, addr)| socket_addr(addr)).collect();
+    let mut iovecs: Vec<libc::iovec> = packets
+        .iter()
+        .map(|(buffer, _)| libc::iovec {
+            iov_base: buffer.as_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(&mut addrs)
+        .map(|(iovec, (addr, length))| {
+            // msghdr has private padding fields on some targets, so it can not be built literally
+            let mut message: libc::mmsghdr = unsafe { mem::zeroed() };
+            message.msg_hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+            message.msg_hdr.msg_namelen = *length;
+            message.msg_hdr.msg_iov = iovec;
+            message.msg_hdr.msg_iovlen = 1;
+            message
+        })
+        .collect();
+    // The buffers, addresses and iovecs outlive the call, and every pointer is to one of them
+    let sent = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            messages.as_mut_ptr(),
+            messages.len() as libc::c_uint,
+            0,
+        )
+    };
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent as usize)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn socket_addr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    use std::mem;
+
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let length = match addr {
+        SocketAddr::V4(addr) => {
+            let storage = &mut storage as *mut _ as *mut libc::sockaddr_in;
+            unsafe {
+                (*storage).sin_family = libc::AF_INET as libc::sa_family_t;
+                (*storage).sin_port = addr.port().to_be();
+                (*storage).sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            }
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let storage = &mut storage as *mut _ as *mut libc::sockaddr_in6;
+            unsafe {
+                (*storage).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                (*storage).sin6_port = addr.port().to_be();
+                (*storage).sin6_flowinfo = addr.flowinfo();
+                (*storage).sin6_addr.s6_addr = addr.ip().octets();
+                (*storage).sin6_scope_id = addr.scope_id();
+            }
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, length as libc::socklen_t)
+}
+
+/// Other platforms have no `sendmmsg`, so packets are sent one by one
+#[cfg(not(target_os = "linux"))]
+fn send_many(socket: &UdpSocket, packets: &[(Vec<u8>, SocketAddr)]) -> io::Result<usize> {
+    let mut sent = 0;
+    for (buffer, addr) in packets {
+        match socket.send_to(buffer, addr) {
+            Ok(_) => sent += 1,
+            Err(e) if sent == 0 => return Err(e),
+            Err(_) => break,
+        }
+    }
+    Ok(sent)
+}
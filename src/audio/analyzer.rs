@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+/// The amount of samples that are analyzed at once, must be a power of two
+pub const WINDOW_SIZE: usize = 1024;
+/// The amount of frequency bands, one for every column of a torch
+pub const BANDS: usize = 7;
+
+const MIN_FREQUENCY: f32 = 40.;
+const MAX_FREQUENCY: f32 = 16_000.;
+/// Band magnitudes are mapped from this many decibels up to 0 dB
+const DYNAMIC_RANGE: f32 = 60.;
+/// About a second of bass energy at 44.1 kHz
+const BEAT_HISTORY: usize = 43;
+const BEAT_THRESHOLD: f32 = 1.5;
+/// Minimum amount of windows between two beats, about 190 ms at 44.1 kHz
+const BEAT_COOLDOWN: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioFeatures {
+    /// RMS level, between 0 and 1
+    pub level: f32,
+    /// Magnitude of each frequency band between 0 and 1, from low to high
+    pub bands: [f32; BANDS],
+    /// Set when a beat onset was detected in this window
+    pub beat: bool,
+}
+
+pub struct Analyzer {
+    window: Vec<f32>,
+    band_bins: [(usize, usize); BANDS],
+    bass_history: VecDeque<f32>,
+    windows_since_beat: usize,
+}
+
+impl Analyzer {
+    pub fn new(sample_rate: u32) -> Analyzer {
+        let window = (0..WINDOW_SIZE)
+            .map(|i| 0.5 - 0.5 * (2. * PI * i as f32 / WINDOW_SIZE as f32).cos())
+            .collect();
+
+        // Logarithmically spaced bands, each at least one bin wide
+        let bin_width = sample_rate as f32 / WINDOW_SIZE as f32;
+        let mut band_bins = [(0, 0); BANDS];
+        let mut start = ((MIN_FREQUENCY / bin_width) as usize).max(1);
+        for (index, bins) in band_bins.iter_mut().enumerate() {
+            let frequency = MIN_FREQUENCY
+                * (MAX_FREQUENCY / MIN_FREQUENCY).powf((index + 1) as f32 / BANDS as f32);
+            let end = ((frequency / bin_width) as usize)
+                .max(start + 1)
+                .min(WINDOW_SIZE / 2);
+            *bins = (start.min(end - 1), end);
+            start = end;
+        }
+
+        Analyzer {
+            window,
+            band_bins,
+            bass_history: VecDeque::with_capacity(BEAT_HISTORY),
+            windows_since_beat: BEAT_COOLDOWN,
+        }
+    }
+
+    /// Analyzes `WINDOW_SIZE` mono samples between -1 and 1
+    pub fn analyze(&mut self, samples: &[f32]) -> AudioFeatures {
+        assert_eq!(samples.len(), WINDOW_SIZE);
+        let mut features = AudioFeatures::default();
+
+        let square_sum: f32 = samples.iter().map(|s| s * s).sum();
+        features.level = ((square_sum / WINDOW_SIZE as f32).sqrt() * 2f32.sqrt()).min(1.);
+
+        let mut buffer: Vec<(f32, f32)> = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| (s * w, 0.))
+            .collect();
+        fft(&mut buffer);
+        // A full scale sine wave results in an amplitude of 1
+        let scale = 4. / WINDOW_SIZE as f32;
+        let magnitudes: Vec<f32> = buffer[..WINDOW_SIZE / 2]
+            .iter()
+            .map(|(re, im)| (re * re + im * im).sqrt() * scale)
+            .collect();
+
+        for (band, (start, end)) in features.bands.iter_mut().zip(&self.band_bins) {
+            let peak = magnitudes[*start..*end]
+                .iter()
+                .cloned()
+                .fold(0f32, f32::max);
+            let decibel = 20. * peak.max(1e-6).log10();
+            *band = ((decibel + DYNAMIC_RANGE) / DYNAMIC_RANGE).clamp(0., 1.);
+        }
+
+        // Beat onsets: the bass energy jumps well above the average of the last second
+        let (start, end) = (self.band_bins[0].0, self.band_bins[1].1);
+        let bass: f32 = magnitudes[start..end].iter().map(|m| m * m).sum();
+        let average = if self.bass_history.is_empty() {
+            0.
+        } else {
+            self.bass_history.iter().sum::<f32>() / self.bass_history.len() as f32
+        };
+        self.windows_since_beat += 1;
+        if self.bass_history.len() == BEAT_HISTORY
+            && bass > average * BEAT_THRESHOLD
+            && bass > 1e-4
+            && self.windows_since_beat >= BEAT_COOLDOWN
+        {
+            features.beat = true;
+            self.windows_since_beat = 0;
+        }
+        if self.bass_history.len() == BEAT_HISTORY {
+            self.bass_history.pop_front();
+        }
+        self.bass_history.push_back(bass);
+
+        features
+    }
+}
+
+/// In-place iterative radix-2 FFT on (real, imaginary) pairs
+fn fft(buffer: &mut [(f32, f32)]) {
+    let n = buffer.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            buffer.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let angle = -2. * PI / length as f32;
+        for chunk in buffer.chunks_mut(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (re, im) = chunk[k + length / 2];
+                let odd = (re * cos - im * sin, re * sin + im * cos);
+                let even = chunk[k];
+                chunk[k] = (even.0 + odd.0, even.1 + odd.1);
+                chunk[k + length / 2] = (even.0 - odd.0, even.1 - odd.1);
+            }
+        }
+        length <<= 1;
+    }
+}
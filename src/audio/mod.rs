@@ -0,0 +1,84 @@
+mod analyzer;
+mod render;
+mod source;
+
+pub use self::analyzer::{Analyzer, AudioFeatures, BANDS, WINDOW_SIZE};
+pub use self::render::{beat_flash, spectrum, vu};
+pub use self::source::Source;
+
+use crate::config::AudioConfig;
+use crate::messages::AudioUpdate;
+use crate::Result;
+use actix::Recipient;
+use std::thread;
+
+/// How fast the level and bands fall back after a peak, per update
+const RELEASE: f32 = 0.85;
+/// Time in seconds for a beat flash to fade to about a third
+const BEAT_DECAY: f64 = 0.15;
+
+/// Reads and analyzes audio on a background thread, sending the results to `recipient`
+pub fn start(config: AudioConfig, recipient: Recipient<AudioUpdate>) -> Result<()> {
+    let mut source = Source::open(&config)?;
+    println!(
+        "Analyzing audio from {:?} at {} Hz",
+        config.source, source.sample_rate
+    );
+    thread::Builder::new()
+        .name(String::from("audio"))
+        .spawn(move || {
+            let mut analyzer = Analyzer::new(source.sample_rate);
+            loop {
+                match source.next_window(WINDOW_SIZE) {
+                    Ok(Some(window)) => {
+                        let features = analyzer.analyze(&window);
+                        if let Err(e) = recipient.do_send(AudioUpdate(features)) {
+                            println!("Could not send audio features: {:?}", e);
+                            return;
+                        }
+                    }
+                    Ok(None) => {
+                        println!("Audio source ended");
+                        return;
+                    }
+                    Err(e) => {
+                        println!("Could not read audio: {:?}", e);
+                        return;
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// The most recent audio features, smoothed so they can be rendered at any rate
+#[derive(Default)]
+pub struct AudioState {
+    pub level: f32,
+    pub bands: [f32; BANDS],
+    /// Amount of beats detected since startup
+    pub beats: usize,
+    pub last_beat: f64,
+}
+
+impl AudioState {
+    pub fn update(&mut self, features: &AudioFeatures, now: f64) {
+        // Instant attack, slow release
+        self.level = features.level.max(self.level * RELEASE);
+        for (band, new) in self.bands.iter_mut().zip(&features.bands) {
+            *band = new.max(*band * RELEASE);
+        }
+        if features.beat {
+            self.beats += 1;
+            self.last_beat = now;
+        }
+    }
+
+    /// 1 right after a beat, fading out to 0
+    pub fn beat_intensity(&self, now: f64) -> f32 {
+        if self.beats == 0 {
+            return 0.;
+        }
+        (-(now - self.last_beat) / BEAT_DECAY).exp() as f32
+    }
+}
@@ -0,0 +1,34 @@
+use super::BANDS;
+use crate::messages::AnimationFrame;
+
+const ROWS: usize = 22;
+
+/// Lights up the torch from the bottom, the amount of rows depends on `level`
+pub fn vu(level: f32, color: (u8, u8, u8)) -> AnimationFrame {
+    let lit_rows = (level.clamp(0., 1.) * ROWS as f32).round() as usize;
+    let mut frame = AnimationFrame::default();
+    for row in frame.iter_mut().rev().take(lit_rows) {
+        *row = [color; 7];
+    }
+    frame
+}
+
+/// The whole torch in `color`, dimmed by `intensity`
+pub fn beat_flash(intensity: f32, (r, g, b): (u8, u8, u8)) -> AnimationFrame {
+    let intensity = intensity.clamp(0., 1.);
+    let dim = |c: u8| (f32::from(c) * intensity) as u8;
+    [[(dim(r), dim(g), dim(b)); 7]; ROWS]
+}
+
+/// A bar for every frequency band from the bottom up, going from green to red
+pub fn spectrum(bands: &[f32; BANDS]) -> AnimationFrame {
+    let mut frame = AnimationFrame::default();
+    for (column, band) in bands.iter().enumerate() {
+        let height = (band.clamp(0., 1.) * ROWS as f32).round() as usize;
+        for height in 0..height {
+            let red = (height * 255 / (ROWS - 1)) as u8;
+            frame[ROWS - 1 - height][column] = (red, 255 - red, 0);
+        }
+    }
+    frame
+}
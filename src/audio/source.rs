@@ -0,0 +1,156 @@
+use crate::config::{AudioConfig, AudioSource};
+use crate::Result;
+use hound::{SampleFormat, WavReader};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Reads PCM audio from a WAV file, stdin or a UDP stream.
+///
+/// Stdin and UDP are expected to contain signed 16-bit little endian samples, interleaved if there are multiple channels.
+pub struct Source {
+    kind: SourceKind,
+    channels: usize,
+    pub sample_rate: u32,
+    /// Interleaved samples that have been read but not returned yet
+    pending: VecDeque<f32>,
+}
+
+enum SourceKind {
+    Wav {
+        reader: WavReader<BufReader<File>>,
+        looping: bool,
+        started: Instant,
+        frames_returned: u64,
+    },
+    Stdin(io::Stdin),
+    Udp(UdpSocket),
+}
+
+impl Source {
+    pub fn open(config: &AudioConfig) -> Result<Source> {
+        let (kind, channels, sample_rate) = match &config.source {
+            AudioSource::Wav { path, looping } => {
+                let reader = WavReader::open(path)?;
+                if reader.duration() == 0 {
+                    bail!("WAV file {:?} contains no samples", path);
+                }
+                let spec = reader.spec();
+                let kind = SourceKind::Wav {
+                    reader,
+                    looping: *looping,
+                    started: Instant::now(),
+                    frames_returned: 0,
+                };
+                (kind, spec.channels, spec.sample_rate)
+            }
+            AudioSource::Stdin => (
+                SourceKind::Stdin(io::stdin()),
+                config.channels,
+                config.sample_rate,
+            ),
+            AudioSource::Udp(addr) => (
+                SourceKind::Udp(UdpSocket::bind(addr)?),
+                config.channels,
+                config.sample_rate,
+            ),
+        };
+        if channels == 0 {
+            bail!("Audio source has no channels");
+        }
+        Ok(Source {
+            kind,
+            channels: usize::from(channels),
+            sample_rate,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Returns the next `size` mono samples between -1 and 1, or `None` when the source has ended
+    pub fn next_window(&mut self, size: usize) -> Result<Option<Vec<f32>>> {
+        while self.pending.len() < size * self.channels {
+            if !self.fill()? {
+                return Ok(None);
+            }
+        }
+        let channels = self.channels;
+        let pending = &mut self.pending;
+        let window = (0..size)
+            .map(|_| pending.drain(..channels).sum::<f32>() / channels as f32)
+            .collect();
+
+        // Files are read faster than real time, so wait until the window would have been played
+        if let SourceKind::Wav {
+            started,
+            frames_returned,
+            ..
+        } = &mut self.kind
+        {
+            *frames_returned += size as u64;
+            let target =
+                Duration::from_secs_f64(*frames_returned as f64 / f64::from(self.sample_rate));
+            if let Some(remaining) = target.checked_sub(started.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+        Ok(Some(window))
+    }
+
+    /// Reads more samples into `pending`, returns false when the source has ended
+    fn fill(&mut self) -> Result<bool> {
+        match &mut self.kind {
+            SourceKind::Wav {
+                reader, looping, ..
+            } => {
+                let spec = reader.spec();
+                let before = self.pending.len();
+                match spec.sample_format {
+                    SampleFormat::Float => {
+                        for sample in reader.samples::<f32>().take(4096) {
+                            self.pending.push_back(sample?);
+                        }
+                    }
+                    SampleFormat::Int => {
+                        let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                        for sample in reader.samples::<i32>().take(4096) {
+                            self.pending.push_back(sample? as f32 / max);
+                        }
+                    }
+                }
+                if self.pending.len() == before {
+                    if !*looping {
+                        return Ok(false);
+                    }
+                    reader.seek(0)?;
+                }
+                Ok(true)
+            }
+            SourceKind::Stdin(stdin) => {
+                let mut buffer = vec![0u8; 1024 * self.channels * 2];
+                match stdin.lock().read_exact(&mut buffer) {
+                    Ok(()) => {}
+                    Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                    Err(e) => return Err(e.into()),
+                }
+                push_s16le(&mut self.pending, &buffer);
+                Ok(true)
+            }
+            SourceKind::Udp(socket) => {
+                let mut buffer = [0u8; 65536];
+                let length = socket.recv(&mut buffer)?;
+                push_s16le(&mut self.pending, &buffer[..length]);
+                Ok(true)
+            }
+        }
+    }
+}
+
+fn push_s16le(pending: &mut VecDeque<f32>, bytes: &[u8]) {
+    for sample in bytes.chunks_exact(2) {
+        let sample = i16::from_le_bytes([sample[0], sample[1]]);
+        pending.push_back(f32::from(sample) / 32768.);
+    }
+}
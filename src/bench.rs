@@ -0,0 +1,88 @@
+//! `reality_lights bench [clients]` measures rendering and sending the frames of a large
+//! installation.
+//!
+//! It uses the config and animations in the working directory. The simulated torches show every
+//! animation in turn, and every fourth one a color. Their frames are sent to closed ports on
+//! the loopback interface.
+
+use crate::artnet::{Client, DmxInput, RenderMode};
+use crate::config::ArtnetInputConfig;
+use crate::service::Service;
+use crate::Result;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+pub const DEFAULT_CLIENTS: usize = 1000;
+/// Ten seconds of rendering
+const TICKS: usize = 300;
+/// The port of the first simulated torch, every torch gets its own
+const FIRST_PORT: u16 = 20000;
+
+pub fn run(clients: usize) -> Result<()> {
+    if clients > usize::from(u16::MAX - FIRST_PORT) {
+        bail!("At most {} clients are supported", u16::MAX - FIRST_PORT);
+    }
+    let mut service = Service::default();
+    let animations = service.animation_names();
+    for index in 0..clients {
+        let address = Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + index as u32 + 1);
+        let mode = if index % 4 == 3 || animations.is_empty() {
+            RenderMode::Color(255, 0, index as u8)
+        } else {
+            RenderMode::Animation(animations[index % animations.len()].clone())
+        };
+        let reply = DmxInput::new(ArtnetInputConfig {
+            address,
+            universe: 0,
+            start_address: 1,
+            short_name: format!("Torch {}", index),
+            long_name: format!("Benchmark torch {}", index),
+        })
+        .poll_reply();
+        let port = FIRST_PORT + index as u16;
+        service.add_client(Client::new(
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port),
+            &reply,
+            mode,
+        )?);
+    }
+    // Non-blocking like the Art-Net socket, so a full send buffer drops frames here too
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    socket.set_nonblocking(true)?;
+    service.set_socket(socket);
+    println!(
+        "Rendering {} ticks for {} torches, with {} animations",
+        TICKS,
+        clients,
+        animations.len()
+    );
+
+    let mut render_time = 0.;
+    for tick in 0..TICKS {
+        let now = tick as f64 / 30.;
+        let start = time::precise_time_s();
+        let frames = service.render_frames(now);
+        render_time += time::precise_time_s() - start;
+        service.send_frames(frames, now);
+    }
+    let stats = service.frame_stats();
+    println!(
+        "Render: {:.3} ms per tick, {:.2} µs per torch",
+        render_time * 1000. / TICKS as f64,
+        render_time * 1_000_000. / (TICKS * clients) as f64
+    );
+    println!(
+        "Send: {:.3} ms per tick on average, {:.3} ms at most, {:.2} µs per frame",
+        stats.average_batch_time * 1000.,
+        stats.max_batch_time * 1000.,
+        stats.average_batch_time * stats.batches as f64 * 1_000_000.
+            / (stats.sent + stats.dropped).max(1) as f64
+    );
+    println!(
+        "{} frames sent, {} skipped, {} dropped ({:.2}%)",
+        stats.sent,
+        stats.skipped,
+        stats.dropped,
+        stats.drop_rate * 100.
+    );
+    Ok(())
+}
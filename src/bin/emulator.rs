@@ -0,0 +1,95 @@
+//! Emulates Art-Net torches, to run the server without hardware.
+//!
+//! Every torch binds port 6454 on its own IP, so use loopback (127.0.0.x) or alias IPs and add
+//! them to `broadcasts` in `config.json`. Run with `--help` for the options.
+
+#[macro_use]
+extern crate failure;
+
+use reality_lights::emulator::{Emulator, Options};
+use reality_lights::Result;
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: emulator [options]
+  --torches N          Amount of torches (default 4)
+  --first-ip IP        IP of the first torch, the others get the following IPs (default 127.0.0.10)
+  --universe U         Port-Address the torches listen to (default 0)
+  --loss P             Fraction of received packets that is dropped, 0 to 1 (default 0)
+  --reboot-every S     Reboot every torch every S seconds, which clears its frame (default never)
+  --offline-every S    Take every torch offline every S seconds (default never)
+  --offline-for S      Seconds a torch stays offline (default 10)";
+
+/// Seconds between printing the statistics
+const STATS_INTERVAL: u64 = 5;
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+    let emulator = match Emulator::start(options.clone()) {
+        Ok(emulator) => emulator,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let broadcasts: Vec<String> = emulator
+        .broadcasts()
+        .iter()
+        .map(|addr| format!("\"{}\"", addr))
+        .collect();
+    println!("Emulating {} torches", options.torches);
+    println!("\"broadcasts\": [{}]", broadcasts.join(", "));
+
+    let mut previous_frames = vec![0; emulator.torches.len()];
+    loop {
+        thread::sleep(Duration::from_secs(STATS_INTERVAL));
+        for ((ip, stats), previous) in emulator.torches.iter().zip(&mut previous_frames) {
+            let stats = stats.lock().expect("Poisoned");
+            println!(
+                "{:<15} {:<7} polls {:<5} frames {:<7} ({:>3} fps) dropped {:<5} reboots {:<3} firmware blocks {:<5} pixel {:?}",
+                ip.to_string(),
+                if stats.online { "online" } else { "offline" },
+                stats.polls,
+                stats.frames,
+                (stats.frames - *previous) / STATS_INTERVAL,
+                stats.dropped,
+                stats.reboots,
+                stats.firmware_blocks,
+                stats.pixel,
+            );
+            *previous = stats.frames;
+        }
+    }
+}
+
+fn parse_options() -> Result<Options> {
+    let mut options = Options::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" {
+            bail!("Art-Net torch emulator");
+        }
+        let value = match args.next() {
+            Some(value) => value,
+            None => bail!("Missing value for {}", arg),
+        };
+        match arg.as_str() {
+            "--torches" => options.torches = value.parse()?,
+            "--first-ip" => options.first_ip = value.parse()?,
+            "--universe" => options.universe = value.parse()?,
+            "--loss" => options.loss = value.parse()?,
+            "--reboot-every" => options.reboot_every = Some(value.parse()?),
+            "--offline-every" => options.offline_every = Some(value.parse()?),
+            "--offline-for" => options.offline_for = value.parse()?,
+            _ => bail!("Unknown option {}", arg),
+        }
+    }
+    Ok(options)
+}
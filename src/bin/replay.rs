@@ -0,0 +1,103 @@
+//! Sends a capture recorded through `/api/recording/start` back out, with the original timing.
+//!
+//! Torches at another venue usually have other IPs, `--map` sends the packets for one IP to
+//! another one instead.
+
+#[macro_use]
+extern crate failure;
+
+use reality_lights::capture::CaptureReader;
+use reality_lights::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::Duration;
+use std::{env, thread};
+
+const USAGE: &str = "Usage: replay <capture file> [options]
+  --loop               Start over at the end of the capture
+  --map FROM=TO        Send the packets for IP FROM to IP TO, can be given multiple times";
+
+struct Options {
+    file: String,
+    repeat: bool,
+    map: HashMap<IpAddr, IpAddr>,
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = run(&options) {
+        eprintln!("Replay failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_options() -> Result<Options> {
+    let mut args = env::args().skip(1);
+    let mut options = Options {
+        file: String::new(),
+        repeat: false,
+        map: HashMap::new(),
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => bail!("Replays a capture of the Art-Net output"),
+            "--loop" => options.repeat = true,
+            "--map" => {
+                let value = match args.next() {
+                    Some(value) => value,
+                    None => bail!("Missing value for --map"),
+                };
+                let mut parts = value.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(from), Some(to)) => {
+                        options.map.insert(from.parse()?, to.parse()?);
+                    }
+                    _ => bail!("--map takes FROM=TO, not {}", value),
+                }
+            }
+            _ if options.file.is_empty() && !arg.starts_with("--") => options.file = arg,
+            _ => bail!("Unknown option {}", arg),
+        }
+    }
+    if options.file.is_empty() {
+        bail!("No capture file given");
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+    loop {
+        let mut reader = CaptureReader::open(&options.file)?;
+        let start = time::precise_time_s();
+        let mut packets = 0;
+        while let Some(packet) = reader.next_packet()? {
+            let wait = packet.time - (time::precise_time_s() - start);
+            if wait > 0. {
+                thread::sleep(Duration::from_secs_f64(wait));
+            }
+            let ip = packet.addr.ip();
+            let addr = SocketAddr::new(*options.map.get(&ip).unwrap_or(&ip), packet.addr.port());
+            // A torch that is not there should not stop the rest of the show
+            if let Err(e) = socket.send_to(&packet.data, addr) {
+                println!("Could not send to {}: {}", addr, e);
+            }
+            packets += 1;
+        }
+        println!(
+            "Replayed {} packets in {:.1} seconds",
+            packets,
+            time::precise_time_s() - start
+        );
+        if !options.repeat {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::Result;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+pub const DIRECTORY: &str = "captures";
+const MAGIC: &[u8; 8] = b"RLCAPT01";
+/// Seconds between flushes, so a recording that is not finished loses at most this much
+const FLUSH_INTERVAL: f64 = 1.;
+
+/// Writes outgoing packets to a capture file.
+///
+/// The file starts with `RLCAPT01`, followed by a record per packet: the seconds since the start
+/// of the recording as a little endian f64, the IP version (4 or 6), the IP, the port and the
+/// length of the packet as big endian u16, and the encoded packet.
+pub struct CaptureWriter {
+    file: BufWriter<File>,
+    path: String,
+    start: f64,
+    last_flush: f64,
+    packets: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecordingStatus {
+    pub recording: bool,
+    pub file: Option<String>,
+    pub seconds: f64,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// A packet read back from a capture file
+pub struct CapturedPacket {
+    /// Seconds since the start of the recording
+    pub time: f64,
+    pub addr: SocketAddr,
+    pub data: Vec<u8>,
+}
+
+pub struct CaptureReader {
+    file: BufReader<File>,
+}
+
+impl CaptureWriter {
+    /// Starts a recording in the captures directory, named after the current time
+    pub fn create(now: f64) -> Result<CaptureWriter> {
+        let _ = fs::create_dir(DIRECTORY);
+        let name = time::strftime("%Y-%m-%d_%H-%M-%S", &time::now())?;
+        let path = format!("{}/{}.capture", DIRECTORY, name);
+        let mut file = BufWriter::new(File::create(&path)?);
+        file.write_all(MAGIC)?;
+        Ok(CaptureWriter {
+            file,
+            path,
+            start: now,
+            last_flush: now,
+            packets: 0,
+            bytes: 0,
+        })
+    }
+
+    pub fn write(&mut self, addr: SocketAddr, data: &[u8], now: f64) -> Result<()> {
+        let file = &mut self.file;
+        file.write_all(&(now - self.start).to_le_bytes())?;
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                file.write_all(&[4])?;
+                file.write_all(&ip.octets())?;
+            }
+            IpAddr::V6(ip) => {
+                file.write_all(&[6])?;
+                file.write_all(&ip.octets())?;
+            }
+        }
+        file.write_all(&addr.port().to_be_bytes())?;
+        file.write_all(&(data.len() as u16).to_be_bytes())?;
+        file.write_all(data)?;
+        self.packets += 1;
+        self.bytes += data.len() as u64;
+        if now - self.last_flush >= FLUSH_INTERVAL {
+            self.file.flush()?;
+            self.last_flush = now;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<String> {
+        self.file.flush()?;
+        Ok(self.path)
+    }
+
+    pub fn status(&self, now: f64) -> RecordingStatus {
+        RecordingStatus {
+            recording: true,
+            file: Some(self.path.clone()),
+            seconds: now - self.start,
+            packets: self.packets,
+            bytes: self.bytes,
+        }
+    }
+}
+
+impl RecordingStatus {
+    pub fn idle() -> RecordingStatus {
+        RecordingStatus {
+            recording: false,
+            file: None,
+            seconds: 0.,
+            packets: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl CaptureReader {
+    pub fn open(path: &str) -> Result<CaptureReader> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("{} is not a capture file", path);
+        }
+        Ok(CaptureReader { file })
+    }
+
+    /// Reads the next packet, or `None` at the end of the file.
+    /// A record cut off by stopping the server while recording also ends the capture.
+    pub fn next_packet(&mut self) -> Result<Option<CapturedPacket>> {
+        match self.read_packet() {
+            Ok(packet) => Ok(Some(packet)),
+            Err(e) => {
+                let eof = e
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof);
+                if eof {
+                    Ok(None)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<CapturedPacket> {
+        let time = self.read_bytes(8)?;
+        let ip = match self.read_bytes(1)?[0] {
+            4 => {
+                let b = self.read_bytes(4)?;
+                IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            6 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&self.read_bytes(16)?);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            version => bail!("Unknown IP version {} in capture", version),
+        };
+        let port = self.read_bytes(2)?;
+        let length = self.read_bytes(2)?;
+        let data = self.read_bytes(usize::from(u16::from_be_bytes([length[0], length[1]])))?;
+        let mut seconds = [0; 8];
+        seconds.copy_from_slice(&time);
+        Ok(CapturedPacket {
+            time: f64::from_le_bytes(seconds),
+            addr: SocketAddr::new(ip, u16::from_be_bytes([port[0], port[1]])),
+            data,
+        })
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; length];
+        self.file.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+}
@@ -1,22 +1,106 @@
+use crate::artnet::RenderMode;
 use crate::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::HashMap;
 use std::fs::File;
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub web_endpoint: SocketAddr,
     pub broadcasts: Vec<SocketAddr>,
     pub torch_mappings: Vec<TorchMap>,
+    #[serde(default)]
+    pub brightness: BrightnessConfig,
+    #[serde(default)]
+    pub calibration: CalibrationConfig,
+    #[serde(default)]
+    pub flash_limiter: FlashLimiterConfig,
+    /// The mode of a newly discovered torch
+    #[serde(default = "default_mode")]
+    pub default_mode: RenderMode,
+    /// Overrides of `default_mode`, keyed by the IP or lowercase MAC address (`aa:bb:cc:dd:ee:ff`) of the torch
+    #[serde(default)]
+    pub torch_modes: HashMap<String, RenderMode>,
+    /// Seconds without a poll reply after which a torch is considered offline
+    #[serde(default = "default_client_timeout")]
+    pub client_timeout: f64,
+    /// Seconds after which an unchanged frame is sent again, so torches do not time out
+    #[serde(default = "default_keepalive")]
+    pub keepalive: f64,
+    /// The file that torch modes and other runtime changes are stored in
+    #[serde(default = "default_state_file")]
+    pub state_file: String,
+    /// Audio input for the audio-reactive modes, disabled if not set
+    #[serde(default)]
+    pub audio: Option<AudioConfig>,
+    /// Address to listen for OSC messages on, disabled if not set
+    #[serde(default)]
+    pub osc_endpoint: Option<SocketAddr>,
+    /// Named groups of torches, by IP
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub scenes: Vec<Scene>,
+    /// Act as an Art-Net node that lighting consoles can control, disabled if not set
+    #[serde(default)]
+    pub artnet_input: Option<ArtnetInputConfig>,
+    #[serde(default)]
+    pub external: ExternalConfig,
+    /// Actions for incoming ArtTrigger packets
+    #[serde(default)]
+    pub triggers: Vec<TriggerConfig>,
+    /// Cues that follow incoming ArtTimeCode while timecode lock is on
+    #[serde(default)]
+    pub timeline: TimelineConfig,
+    /// Run as the primary or standby of a redundant pair, disabled if not set
+    #[serde(default)]
+    pub redundancy: Option<RedundancyConfig>,
+}
+
+fn default_mode() -> RenderMode {
+    RenderMode::Color(0, 255, 0)
+}
+
+fn default_client_timeout() -> f64 {
+    5.
+}
+
+fn default_keepalive() -> f64 {
+    1.
+}
+
+fn default_state_file() -> String {
+    String::from("state.json")
 }
 
 impl Config {
     pub fn from_file(file: &str) -> Result<Config> {
         let mut file = File::open(file)?;
         let config: Config = serde_json::from_reader(&mut file)?;
+        for (name, profile) in &config.calibration.profiles {
+            profile
+                .validate()
+                .map_err(|e| format_err!("Calibration profile {:?}: {}", name, e))?;
+        }
         Ok(config)
     }
+
+    pub fn mode_for(&self, ip: &str, mac: Option<&str>) -> RenderMode {
+        mac.and_then(|mac| self.torch_modes.get(mac))
+            .or_else(|| self.torch_modes.get(ip))
+            .unwrap_or(&self.default_mode)
+            .clone()
+    }
+}
+
+/// A mode for a set of torches, that can be recalled at once
+#[derive(Deserialize, Debug, Clone)]
+pub struct Scene {
+    pub name: String,
+    /// The mode of each torch, keyed by IP. Torches that are not listed keep their current mode.
+    pub torches: HashMap<String, RenderMode>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -40,3 +124,265 @@ pub enum Side {
     Middle,
     Outside,
 }
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BrightnessConfig {
+    /// Global dimmer in percent, applied to every torch
+    pub master: u8,
+    /// The value a full (255) channel is scaled down to, because the torches can overheat
+    pub max_channel: u8,
+    /// Maximum sum of all channels in a single frame. Frames above this are scaled down.
+    pub max_power: Option<u32>,
+    /// Overrides of `max_channel` and `max_power`, keyed by the IP of the torch
+    pub torches: HashMap<String, TorchLimits>,
+}
+
+impl Default for BrightnessConfig {
+    fn default() -> BrightnessConfig {
+        BrightnessConfig {
+            master: 100,
+            max_channel: 100,
+            max_power: None,
+            torches: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TorchLimits {
+    pub max_channel: Option<u8>,
+    pub max_power: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CalibrationConfig {
+    /// The profile used for torches that are not listed in `torches`
+    pub default: Option<String>,
+    pub profiles: HashMap<String, CalibrationProfile>,
+    /// The name of the profile for each torch, keyed by the IP of the torch
+    pub torches: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct CalibrationProfile {
+    /// Gamma curve for the red, green and blue channel
+    pub gamma: [f32; 3],
+    /// Multipliers for the red, green and blue channel. Ignored if `matrix` is set.
+    pub gains: [f32; 3],
+    /// Color correction matrix, each row produces the red, green and blue output respectively
+    pub matrix: Option<[[f32; 3]; 3]>,
+}
+
+impl CalibrationProfile {
+    pub fn validate(&self) -> Result<()> {
+        // Also rejects NaN, which would turn the gamma table into garbage
+        if !self.gamma.iter().all(|gamma| *gamma > 0.) {
+            bail!("Gamma invalid, should be larger than 0");
+        }
+        Ok(())
+    }
+}
+
+impl Default for CalibrationProfile {
+    fn default() -> CalibrationProfile {
+        CalibrationProfile {
+            gamma: [1.0; 3],
+            gains: [1.0; 3],
+            matrix: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct FlashLimiterConfig {
+    pub enabled: bool,
+    pub max_flashes_per_second: u8,
+    /// The minimum change in average luminance (0-255) that counts as half a flash
+    pub min_transition: u8,
+    pub mode: FlashLimiterMode,
+}
+
+impl Default for FlashLimiterConfig {
+    fn default() -> FlashLimiterConfig {
+        FlashLimiterConfig {
+            enabled: false,
+            max_flashes_per_second: 3,
+            min_transition: 20,
+            mode: FlashLimiterMode::Smooth,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum FlashLimiterMode {
+    /// Limit the change in luminance to just below `min_transition`
+    Smooth,
+    /// Keep showing the previous frame
+    Suppress,
+}
+
+/// Settings of torches in `RenderMode::External`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExternalConfig {
+    pub merge: MergeMode,
+    /// Seconds without an ArtDmx packet after which only the local mode is shown
+    pub timeout: f64,
+}
+
+impl Default for ExternalConfig {
+    fn default() -> ExternalConfig {
+        ExternalConfig {
+            merge: MergeMode::Htp,
+            timeout: 2.,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum MergeMode {
+    /// Highest takes precedence, every channel is the maximum of the external and local value
+    Htp,
+    /// Latest takes precedence, the external stream replaces the local output while it is active
+    Ltp,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TriggerConfig {
+    /// The OEM code of the trigger, 0xFFFF (the default) for the standard keys
+    #[serde(default = "default_trigger_oem")]
+    pub oem: u16,
+    pub key: u8,
+    pub sub_key: u8,
+    pub action: Action,
+}
+
+fn default_trigger_oem() -> u16 {
+    0xFFFF
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum Action {
+    /// Recall the scene with this name
+    Scene(String),
+    /// Play an animation once and return to the previous mode
+    OneShot {
+        animation: String,
+        /// IPs and group names, all torches if empty
+        #[serde(default)]
+        torches: Vec<String>,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TimelineConfig {
+    /// Follow incoming ArtTimeCode from the start, can be changed through the API
+    pub lock: bool,
+    pub cues: Vec<Cue>,
+}
+
+impl Default for TimelineConfig {
+    fn default() -> TimelineConfig {
+        TimelineConfig {
+            lock: true,
+            cues: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Cue {
+    /// Position in the timecode, in seconds
+    pub at: f64,
+    pub action: Action,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AudioConfig {
+    pub source: AudioSource,
+    /// Sample rate of `Stdin` and `Udp` sources, WAV files contain their own
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: u32,
+    /// Amount of interleaved channels of `Stdin` and `Udp` sources, WAV files contain their own
+    #[serde(default = "default_channels")]
+    pub channels: u16,
+}
+
+fn default_sample_rate() -> u32 {
+    44_100
+}
+
+fn default_channels() -> u16 {
+    1
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum AudioSource {
+    Wav {
+        path: String,
+        #[serde(default)]
+        looping: bool,
+    },
+    /// Signed 16-bit little endian PCM
+    Stdin,
+    /// Datagrams of signed 16-bit little endian PCM, received on the given address
+    Udp(SocketAddr),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ArtnetInputConfig {
+    /// The IP address of this server, as reported to consoles
+    pub address: Ipv4Addr,
+    /// The 15-bit Port-Address that consoles send to
+    #[serde(default)]
+    pub universe: u16,
+    /// The first DMX channel, starting at 1
+    #[serde(default = "default_start_address")]
+    pub start_address: u16,
+    #[serde(default = "default_short_name")]
+    pub short_name: String,
+    #[serde(default = "default_long_name")]
+    pub long_name: String,
+}
+
+fn default_start_address() -> u16 {
+    1
+}
+
+fn default_short_name() -> String {
+    String::from("reality_lights")
+}
+
+fn default_long_name() -> String {
+    String::from("Reality lights torch controller")
+}
+
+/// Two instances on the same network, of which only the active one sends to the torches
+#[derive(Deserialize, Debug, Clone)]
+pub struct RedundancyConfig {
+    pub role: Role,
+    /// Address to receive heartbeats from the other instance on
+    pub listen: SocketAddr,
+    /// The `listen` address of the other instance
+    pub peer: SocketAddr,
+    /// Seconds without a heartbeat from the primary after which the standby takes over
+    #[serde(default = "default_takeover_timeout")]
+    pub timeout: f64,
+}
+
+fn default_takeover_timeout() -> f64 {
+    3.
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    /// Always sends, and takes over changes the standby made while it was gone
+    Primary,
+    /// Sends no frames while the primary sends heartbeats
+    Standby,
+}
@@ -0,0 +1,365 @@
+//! Emulated Art-Net torches, used by the `emulator` binary and the integration tests.
+//!
+//! Every torch binds port 6454 on its own IP, so use loopback (127.0.0.x) or alias IPs and add
+//! them to `broadcasts` in `config.json`.
+
+use crate::artnet::rdm::{self, RdmMessage, Uid};
+use crate::artnet::{copy_name, FirmwareBlock, FirmwareReply, IpProgReply, Packet, Rdm, TodData};
+use crate::Result;
+use artnet_protocol::{ArtCommand, PollReply};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Seconds a rebooting torch does not respond
+const REBOOT_TIME: f64 = 3.;
+
+#[derive(Clone)]
+pub struct Options {
+    pub torches: u8,
+    /// The IP of the first torch, the others get the following IPs
+    pub first_ip: Ipv4Addr,
+    /// The Port-Address the torches listen to
+    pub universe: u16,
+    /// Fraction of received packets that is dropped, 0 to 1
+    pub loss: f64,
+    /// Reboot every torch every this many seconds, which clears its frame
+    pub reboot_every: Option<f64>,
+    /// Take every torch offline every this many seconds, for `offline_for` seconds
+    pub offline_every: Option<f64>,
+    pub offline_for: f64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            torches: 4,
+            first_ip: Ipv4Addr::new(127, 0, 0, 10),
+            universe: 0,
+            loss: 0.,
+            reboot_every: None,
+            offline_every: None,
+            offline_for: 10.,
+        }
+    }
+}
+
+/// What a torch received, shared with the thread of the torch
+#[derive(Default)]
+pub struct Stats {
+    pub polls: u64,
+    pub frames: u64,
+    pub dropped: u64,
+    pub reboots: u64,
+    pub online: bool,
+    /// The first pixel of the last received frame
+    pub pixel: (u8, u8, u8),
+    pub firmware_blocks: u64,
+}
+
+/// Torches that answer on a thread each, until the emulator is dropped
+pub struct Emulator {
+    /// The IP and statistics of every torch
+    pub torches: Vec<(Ipv4Addr, Arc<Mutex<Stats>>)>,
+    running: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Emulator {
+    pub fn start(options: Options) -> Result<Emulator> {
+        let running = Arc::new(AtomicBool::new(true));
+        let mut emulator = Emulator {
+            torches: Vec::new(),
+            running,
+            threads: Vec::new(),
+        };
+        for index in 0..options.torches {
+            let ip = Ipv4Addr::from(u32::from(options.first_ip) + u32::from(index));
+            let torch = Torch::new(index, ip, options.clone())
+                .map_err(|e| format_err!("Could not start torch {}: {}", ip, e))?;
+            emulator.torches.push((ip, torch.stats.clone()));
+            let running = emulator.running.clone();
+            emulator
+                .threads
+                .push(thread::spawn(move || torch.run(running)));
+        }
+        Ok(emulator)
+    }
+
+    /// The addresses to put in `broadcasts` to reach every torch
+    pub fn broadcasts(&self) -> Vec<SocketAddr> {
+        self.torches
+            .iter()
+            .map(|(ip, _)| SocketAddr::new((*ip).into(), 6454))
+            .collect()
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Torch {
+    index: u8,
+    ip: Ipv4Addr,
+    socket: UdpSocket,
+    options: Options,
+    short_name: String,
+    long_name: String,
+    universe: u16,
+    uid: Uid,
+    rng: u64,
+    /// The emulator time until which the torch does not respond
+    down_until: f64,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Torch {
+    fn new(index: u8, ip: Ipv4Addr, options: Options) -> Result<Torch> {
+        let builder = net2::UdpBuilder::new_v4()?;
+        builder.reuse_address(true)?;
+        // The server binds 0.0.0.0:6454 on the same machine
+        #[cfg(unix)]
+        net2::unix::UnixUdpBuilderExt::reuse_port(&builder, true)?;
+        let socket = builder.bind(SocketAddr::new(ip.into(), 6454))?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        Ok(Torch {
+            index,
+            ip,
+            socket,
+            short_name: format!("Emulated {}", index + 1),
+            long_name: format!("Emulated torch {} at {}", index + 1, ip),
+            universe: options.universe,
+            options,
+            uid: Uid([0x7F, 0xF1, 0, 0, 0, index]),
+            rng: 0x2545_F491_4F6C_DD1D ^ u64::from(index),
+            down_until: 0.,
+            stats: Arc::new(Mutex::new(Stats::default())),
+        })
+    }
+
+    fn run(mut self, running: Arc<AtomicBool>) {
+        let start = time::precise_time_s();
+        // Spread the reboots and outages of the torches over their interval
+        let offset = f64::from(self.index) / f64::from(self.options.torches);
+        let mut next_reboot = self.options.reboot_every.map(|s| s * (1. + offset));
+        let mut next_offline = self.options.offline_every.map(|s| s * (1. + offset));
+        let mut buffer = [0u8; 2048];
+        while running.load(Ordering::Relaxed) {
+            let now = time::precise_time_s() - start;
+            if let (Some(at), Some(every)) = (next_reboot, self.options.reboot_every) {
+                if now >= at {
+                    println!("{} reboots", self.ip);
+                    self.reboot(now);
+                    next_reboot = Some(at + every);
+                }
+            }
+            if let (Some(at), Some(every)) = (next_offline, self.options.offline_every) {
+                if now >= at {
+                    println!("{} goes offline", self.ip);
+                    self.down_until = self.down_until.max(now + self.options.offline_for);
+                    next_offline = Some(at + every);
+                }
+            }
+            let online = now >= self.down_until;
+            self.stats.lock().expect("Poisoned").online = online;
+
+            let (len, addr) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(_) => continue,
+            };
+            if !online {
+                continue;
+            }
+            if self.random() < self.options.loss {
+                self.stats.lock().expect("Poisoned").dropped += 1;
+                continue;
+            }
+            let replies = match Packet::from_buffer(&buffer[..len]) {
+                Ok(packet) => self.handle(packet, now),
+                Err(e) => {
+                    println!("{} could not decode packet: {}", self.ip, e);
+                    continue;
+                }
+            };
+            for reply in replies {
+                match reply.into_buffer() {
+                    Ok(bytes) => {
+                        if let Err(e) = self.socket.send_to(&bytes, addr) {
+                            println!("{} could not reply: {}", self.ip, e);
+                        }
+                    }
+                    Err(e) => println!("{} could not encode reply: {}", self.ip, e),
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: Packet, now: f64) -> Vec<Packet> {
+        let stats = self.stats.clone();
+        let mut stats = stats.lock().expect("Poisoned");
+        match packet {
+            Packet::Command(ArtCommand::Poll(_)) => {
+                stats.polls += 1;
+                vec![self.poll_reply()]
+            }
+            Packet::Command(ArtCommand::Output(output)) => {
+                if output.subnet == self.universe && output.data.len() >= 3 {
+                    stats.frames += 1;
+                    stats.pixel = (output.data[0], output.data[1], output.data[2]);
+                }
+                Vec::new()
+            }
+            Packet::Address(address) => {
+                if let Some(name) = address.short_name {
+                    self.short_name = name;
+                }
+                if let Some(name) = address.long_name {
+                    self.long_name = name;
+                }
+                if let Some(universe) = address.universe {
+                    self.universe = universe;
+                }
+                vec![self.poll_reply()]
+            }
+            // Emulated torches can not change their IP, so they report the one they have
+            Packet::IpProg(_) => vec![Packet::IpProgReply(IpProgReply {
+                address: self.ip,
+                mask: Ipv4Addr::new(255, 0, 0, 0),
+                dhcp: false,
+            })],
+            Packet::FirmwareMaster(master) => {
+                stats.firmware_blocks += 1;
+                if master.block == FirmwareBlock::Last {
+                    println!("{} received firmware, rebooting", self.ip);
+                    drop(stats);
+                    self.reboot(now);
+                    vec![Packet::FirmwareReply(FirmwareReply::AllGood)]
+                } else {
+                    vec![Packet::FirmwareReply(FirmwareReply::BlockGood)]
+                }
+            }
+            Packet::TodRequest(request) => {
+                let net = (self.universe >> 8) as u8;
+                if request.net != net || !request.addresses.contains(&(self.universe as u8)) {
+                    return Vec::new();
+                }
+                vec![Packet::TodData(TodData {
+                    port: 1,
+                    net,
+                    address: self.universe as u8,
+                    uid_total: 1,
+                    block_count: 0,
+                    uids: vec![self.uid],
+                })]
+            }
+            Packet::Rdm(rdm) if rdm.message.destination == self.uid => {
+                let message = self.rdm_response(&rdm.message);
+                vec![Packet::Rdm(Rdm {
+                    net: rdm.net,
+                    address: rdm.address,
+                    message,
+                })]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn rdm_response(&mut self, request: &RdmMessage) -> RdmMessage {
+        let data = match request.pid {
+            rdm::PID_DEVICE_INFO => {
+                let mut info = vec![0x01, 0x00, 0x00, 0x01, 0x05, 0x09, 0, 0, 0, 1];
+                // Footprint of 450 channels at address 1, no personalities or sub devices
+                info.extend_from_slice(&[0x01, 0xC2, 0, 1, 0, 1, 0, 0, 1]);
+                Some(info)
+            }
+            rdm::PID_DEVICE_LABEL => Some(self.short_name.as_bytes().to_vec()),
+            rdm::PID_DEVICE_MODEL_DESCRIPTION => Some(b"Emulated torch".to_vec()),
+            rdm::PID_LAMP_HOURS => Some(1000u32.to_be_bytes().to_vec()),
+            // Sensor 0 is a temperature sensor in degrees Celsius
+            rdm::PID_SENSOR_DEFINITION => {
+                let mut definition = vec![0, 0x00, 0x01, 0x00, 0, 0, 0, 100, 0, 20, 0, 60, 0];
+                definition.extend_from_slice(b"Temperature");
+                Some(definition)
+            }
+            rdm::PID_SENSOR_VALUE => {
+                let temperature = 35 + (self.random() * 10.) as i16;
+                let mut value = vec![0];
+                value.extend_from_slice(&temperature.to_be_bytes());
+                value.extend_from_slice(&[0; 6]);
+                Some(value)
+            }
+            _ => None,
+        };
+        RdmMessage {
+            destination: request.source,
+            source: self.uid,
+            transaction: request.transaction,
+            // ACK, or NACK with reason UNKNOWN_PID
+            port_or_response: if data.is_some() { 0x00 } else { 0x02 },
+            sub_device: request.sub_device,
+            command_class: rdm::GET_COMMAND_RESPONSE,
+            pid: request.pid,
+            data: data.unwrap_or_else(|| vec![0, 0]),
+        }
+    }
+
+    fn poll_reply(&self) -> Packet {
+        let mut short_name = [0u8; 18];
+        let mut long_name = [0u8; 64];
+        copy_name(&mut short_name, &self.short_name);
+        copy_name(&mut long_name, &self.long_name);
+        let universe = self.universe;
+        Packet::Command(ArtCommand::PollReply(Box::new(PollReply {
+            address: self.ip,
+            port: 0x1936,
+            version: [0, 14],
+            port_address: [(universe >> 8) as u8 & 0x7F, (universe >> 4) as u8 & 0x0F],
+            oem: [0, 0xFF],
+            ubea_version: 0,
+            status_1: 0,
+            esta_code: 0,
+            short_name,
+            long_name,
+            node_report: [0; 64],
+            num_ports: [0, 1],
+            port_types: [0x80, 0, 0, 0],
+            good_input: [0; 4],
+            good_output: [0x80, 0, 0, 0],
+            swin: [0; 4],
+            swout: [universe as u8 & 0x0F, 0, 0, 0],
+            sw_video: 0,
+            sw_macro: 0,
+            sw_remote: 0,
+            spare: [0; 3],
+            style: 0,
+            mac: [0x02, 0, 0x7F, 0xF1, 0, self.index],
+            bind_ip: self.ip.octets(),
+            bind_index: 1,
+            status_2: 0b1000,
+            filler: [0; 26],
+        })))
+    }
+
+    fn reboot(&mut self, now: f64) {
+        self.down_until = self.down_until.max(now + REBOOT_TIME);
+        let mut stats = self.stats.lock().expect("Poisoned");
+        stats.reboots += 1;
+        stats.pixel = (0, 0, 0);
+    }
+
+    /// A random number from 0 to 1, xorshift is plenty for packet loss
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,245 @@
+use crate::artnet::{FirmwareBlock, FirmwareMaster, FirmwareReply, FIRMWARE_BLOCK_SIZE};
+use crate::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+const DIRECTORY: &str = "firmware";
+/// Seconds to wait for an ArtFirmwareReply before sending a block again
+const BLOCK_TIMEOUT: f64 = 5.;
+/// Times a block is sent again before the upload fails
+const MAX_RETRIES: u8 = 3;
+
+/// Firmware images in the `firmware` directory and the uploads of them to torches.
+///
+/// Every block is only sent after the torch confirmed the previous one with an ArtFirmwareReply.
+pub struct FirmwareManager {
+    images: HashMap<String, Arc<Vec<u8>>>,
+    uploads: HashMap<SocketAddr, Upload>,
+}
+
+struct Upload {
+    ip: String,
+    firmware: String,
+    image: Arc<Vec<u8>>,
+    block: usize,
+    last_sent: f64,
+    retries: u8,
+    state: UploadState,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum UploadState {
+    Uploading,
+    /// The torch received everything and installs the firmware
+    Done,
+    Failed(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct FirmwareStatus {
+    /// Firmware names and their size in bytes
+    pub images: HashMap<String, usize>,
+    pub uploads: Vec<UploadStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadStatus {
+    pub ip: String,
+    pub firmware: String,
+    pub blocks_confirmed: usize,
+    pub blocks: usize,
+    pub state: UploadState,
+}
+
+impl FirmwareManager {
+    pub fn load() -> Result<FirmwareManager> {
+        let mut images = HashMap::new();
+        if let Ok(entries) = fs::read_dir(DIRECTORY) {
+            for entry in entries {
+                let path = entry?.path();
+                if let Some(name) = path.file_stem().and_then(|n| n.to_str()) {
+                    println!("Loading firmware {:?}", name);
+                    images.insert(name.to_owned(), Arc::new(fs::read(&path)?));
+                }
+            }
+        }
+        Ok(FirmwareManager {
+            images,
+            uploads: HashMap::new(),
+        })
+    }
+
+    pub fn add(&mut self, name: &str, image: Vec<u8>) -> Result<()> {
+        if name.is_empty() || name.contains(['/', '\\', '.']) {
+            bail!("Firmware name invalid");
+        }
+        if image.is_empty() {
+            bail!("Firmware is empty");
+        }
+        let _ = fs::create_dir(DIRECTORY);
+        fs::write(format!("{}/{}.bin", DIRECTORY, name), &image)?;
+        self.images.insert(name.to_owned(), Arc::new(image));
+        Ok(())
+    }
+
+    pub fn is_uploading(&self, addr: &SocketAddr) -> bool {
+        match self.uploads.get(addr) {
+            Some(upload) => matches!(upload.state, UploadState::Uploading),
+            None => false,
+        }
+    }
+
+    /// Whether an upload of `firmware` to the torch can start
+    pub fn check(&self, addr: &SocketAddr, ip: &str, firmware: &str) -> Result<()> {
+        if self.is_uploading(addr) {
+            bail!("Torch {} is already being updated", ip);
+        }
+        if !self.images.contains_key(firmware) {
+            bail!("Firmware {:?} not found", firmware);
+        }
+        Ok(())
+    }
+
+    /// Starts an upload and returns its first block
+    pub fn start(
+        &mut self,
+        addr: SocketAddr,
+        ip: &str,
+        firmware: &str,
+        now: f64,
+    ) -> Result<FirmwareMaster> {
+        self.check(&addr, ip, firmware)?;
+        let image = self.images[firmware].clone();
+        let upload = Upload {
+            ip: ip.to_owned(),
+            firmware: firmware.to_owned(),
+            image,
+            block: 0,
+            last_sent: now,
+            retries: 0,
+            state: UploadState::Uploading,
+        };
+        let block = upload.block();
+        self.uploads.insert(addr, upload);
+        Ok(block)
+    }
+
+    /// Forgets an upload whose first block could not be sent
+    pub fn cancel(&mut self, addr: &SocketAddr) {
+        self.uploads.remove(addr);
+    }
+
+    /// Handles an ArtFirmwareReply and returns the next block to send, if any
+    pub fn reply(
+        &mut self,
+        addr: &SocketAddr,
+        reply: FirmwareReply,
+        now: f64,
+    ) -> Option<FirmwareMaster> {
+        let upload = self.uploads.get_mut(addr)?;
+        if !matches!(upload.state, UploadState::Uploading) {
+            return None;
+        }
+        let last = upload.block + 1 == upload.blocks();
+        match reply {
+            FirmwareReply::Fail => {
+                upload.fail(format!("Torch rejected block {}", upload.block));
+                None
+            }
+            FirmwareReply::AllGood | FirmwareReply::BlockGood if last => {
+                upload.block += 1;
+                println!(
+                    "Torch {} received firmware {:?}",
+                    upload.ip, upload.firmware
+                );
+                upload.state = UploadState::Done;
+                None
+            }
+            FirmwareReply::AllGood => {
+                upload.fail(format!("Torch finished early, at block {}", upload.block));
+                None
+            }
+            FirmwareReply::BlockGood => {
+                upload.block += 1;
+                upload.last_sent = now;
+                upload.retries = 0;
+                Some(upload.block())
+            }
+        }
+    }
+
+    /// Returns the blocks that were not confirmed in time and should be sent again
+    pub fn tick(&mut self, now: f64) -> Vec<(SocketAddr, FirmwareMaster)> {
+        let mut resend = Vec::new();
+        for (addr, upload) in &mut self.uploads {
+            if !matches!(upload.state, UploadState::Uploading)
+                || now - upload.last_sent < BLOCK_TIMEOUT
+            {
+                continue;
+            }
+            if upload.retries >= MAX_RETRIES {
+                upload.fail(format!("No reply to block {}", upload.block));
+                continue;
+            }
+            upload.retries += 1;
+            upload.last_sent = now;
+            resend.push((*addr, upload.block()));
+        }
+        resend
+    }
+
+    pub fn status(&self) -> FirmwareStatus {
+        FirmwareStatus {
+            images: self
+                .images
+                .iter()
+                .map(|(name, image)| (name.clone(), image.len()))
+                .collect(),
+            uploads: self
+                .uploads
+                .values()
+                .map(|upload| UploadStatus {
+                    ip: upload.ip.clone(),
+                    firmware: upload.firmware.clone(),
+                    blocks_confirmed: upload.block,
+                    blocks: upload.blocks(),
+                    state: upload.state.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Upload {
+    fn blocks(&self) -> usize {
+        self.image.len().div_ceil(FIRMWARE_BLOCK_SIZE)
+    }
+
+    fn block(&self) -> FirmwareMaster {
+        let start = self.block * FIRMWARE_BLOCK_SIZE;
+        let end = (start + FIRMWARE_BLOCK_SIZE).min(self.image.len());
+        FirmwareMaster {
+            block: if self.block + 1 == self.blocks() {
+                FirmwareBlock::Last
+            } else if self.block == 0 {
+                FirmwareBlock::First
+            } else {
+                FirmwareBlock::Continue
+            },
+            block_id: self.block as u8,
+            firmware_length: self.image.len().div_ceil(2) as u32,
+            data: self.image[start..end].to_vec(),
+        }
+    }
+
+    fn fail(&mut self, error: String) {
+        println!(
+            "Firmware upload of {:?} to {} failed: {}",
+            self.firmware, self.ip, error
+        );
+        self.state = UploadState::Failed(error);
+    }
+}
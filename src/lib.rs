@@ -0,0 +1,26 @@
+#![cfg_attr(not(debug_assertions), allow(warnings))]
+
+#[macro_use]
+extern crate failure;
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+pub mod animation_handler;
+pub mod artnet;
+pub mod audio;
+pub mod bench;
+pub mod capture;
+pub mod config;
+pub mod emulator;
+pub mod firmware;
+pub mod messages;
+pub mod metrics;
+pub mod osc;
+pub mod output;
+pub mod rdm;
+pub mod redundancy;
+pub mod service;
+pub mod state;
+pub mod tempo;
+pub mod timeline;
+pub mod web;
@@ -1,22 +1,23 @@
 #![cfg_attr(not(debug_assertions), allow(warnings))]
 
-#[macro_use]
-extern crate failure;
-
-pub type Result<T> = std::result::Result<T, failure::Error>;
-
-mod animation_handler;
-mod artnet;
-mod config;
-mod messages;
-mod service;
-mod web;
-
 use actix::{ArbiterService, System};
+use reality_lights::{bench, osc, service, web};
 
 fn main() {
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("bench") {
+        let clients = match args.next() {
+            Some(clients) => clients
+                .parse()
+                .expect("Amount of clients should be a number"),
+            None => bench::DEFAULT_CLIENTS,
+        };
+        bench::run(clients).expect("Benchmark failed");
+        return;
+    }
     let system = System::new("TR");
     let artnet = service::Service::start_service();
     let _addr = web::run(&artnet);
+    let _osc = osc::run(&artnet).expect("Could not start OSC server");
     system.run().expect("Actix system crashed");
 }
@@ -1,6 +1,17 @@
-use crate::artnet::RenderMode;
+use crate::artnet::{ChangeStatus, DecodeStatus, NodeMetricsStatus, RenderMode, TransportStatus};
+use crate::audio::AudioFeatures;
+use crate::capture::RecordingStatus;
+use crate::config::CalibrationConfig;
+use crate::firmware::FirmwareStatus;
+use crate::metrics::Histogram;
+use crate::rdm::RdmDevice;
+use crate::redundancy::{Heartbeat, RedundancyStatus};
+use crate::state::State;
+use crate::tempo::Tempo;
+use crate::timeline::TimelineStatus;
 use crate::Result;
 use actix::Message;
+use bytes::Bytes;
 use serde::Serialize;
 
 #[derive(Debug)]
@@ -21,6 +32,35 @@ pub struct Node {
     pub short_name: String,
     pub long_name: String,
     pub current: RenderMode,
+    pub flash_violations: u64,
+    /// The Port-Address the torch listens to
+    pub universe: u16,
+    /// The state of the last change made through `/api/address` or `/api/ip_prog`
+    pub change: Option<ChangeStatus>,
+    /// The RDM devices behind the torch
+    pub rdm: Vec<RdmDevice>,
+    pub metrics: NodeMetricsStatus,
+}
+
+#[derive(Debug)]
+pub struct RequestNodeMetrics;
+
+impl Message for RequestNodeMetrics {
+    type Result = Result<ResponseNodeMetrics>;
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseNodeMetrics {
+    pub nodes: Vec<NodeMetricsEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeMetricsEntry {
+    pub ip: String,
+    pub short_name: String,
+    pub online: bool,
+    #[serde(flatten)]
+    pub metrics: NodeMetricsStatus,
 }
 
 #[derive(Debug)]
@@ -50,6 +90,12 @@ pub struct Animation {
     pub name: String,
     #[serde(skip_serializing)]
     pub frames: Vec<AnimationFrame>,
+    /// Every frame encoded as it is sent to a torch, see `output::encode`
+    #[serde(skip_serializing)]
+    pub payloads: Vec<Bytes>,
+    /// Unique for every load, so payloads cached for a replaced animation are not used
+    #[serde(skip_serializing)]
+    pub id: u64,
     pub fps: u8,
 }
 
@@ -58,6 +104,8 @@ impl Default for Animation {
         Animation {
             name: String::new(),
             frames: Vec::new(),
+            payloads: Vec::new(),
+            id: 0,
             fps: 1,
         }
     }
@@ -84,3 +132,289 @@ pub struct SetNodeColor {
 impl Message for SetNodeColor {
     type Result = Result<()>;
 }
+
+#[derive(Debug)]
+pub struct SetMasterBrightness {
+    pub brightness: u8,
+}
+
+impl Message for SetMasterBrightness {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct RequestCalibration;
+
+impl Message for RequestCalibration {
+    type Result = Result<CalibrationConfig>;
+}
+
+#[derive(Debug)]
+pub struct SetCalibration {
+    pub profile: String,
+    pub setting: String,
+    pub values: String,
+}
+
+impl Message for SetCalibration {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct SetNodeCalibration {
+    pub ip: String,
+    pub profile: String,
+}
+
+impl Message for SetNodeCalibration {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct SetBlackout {
+    pub latched: bool,
+}
+
+impl Message for SetBlackout {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct SetNodeAudio {
+    pub ip: String,
+    pub mode: String,
+    pub value: Option<String>,
+}
+
+impl Message for SetNodeAudio {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct AudioUpdate(pub AudioFeatures);
+
+impl Message for AudioUpdate {
+    type Result = ();
+}
+
+/// A heartbeat of the other instance of a redundant pair
+#[derive(Debug)]
+pub struct PeerHeartbeat(pub Heartbeat);
+
+impl Message for PeerHeartbeat {
+    type Result = ();
+}
+
+/// The state of the other instance of a redundant pair, sent while this instance has an older one
+#[derive(Debug)]
+pub struct PeerState(pub State);
+
+impl Message for PeerState {
+    type Result = ();
+}
+
+#[derive(Debug)]
+pub struct RequestTempo;
+
+impl Message for RequestTempo {
+    type Result = Result<Tempo>;
+}
+
+#[derive(Debug)]
+pub struct TapTempo;
+
+impl Message for TapTempo {
+    type Result = Result<Tempo>;
+}
+
+#[derive(Debug)]
+pub struct SetBpm {
+    pub bpm: f64,
+}
+
+impl Message for SetBpm {
+    type Result = Result<Tempo>;
+}
+
+#[derive(Debug)]
+pub struct SetNodeTempoAnimation {
+    pub ip: String,
+    pub animation_name: String,
+    pub frames_per_beat: u8,
+}
+
+impl Message for SetNodeTempoAnimation {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct SetNodeAddress {
+    pub ip: String,
+    pub setting: String,
+    pub value: String,
+}
+
+impl Message for SetNodeAddress {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct SetNodeIp {
+    pub ip: String,
+    /// The new IP address and subnet mask, or `None` for DHCP
+    pub address: Option<(String, String)>,
+}
+
+impl Message for SetNodeIp {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct AddFirmware {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+impl Message for AddFirmware {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct RequestFirmware;
+
+impl Message for RequestFirmware {
+    type Result = Result<FirmwareStatus>;
+}
+
+#[derive(Debug)]
+pub struct PushFirmware {
+    pub firmware: String,
+    /// Comma separated IPs and group names, or `all`
+    pub torches: String,
+}
+
+impl Message for PushFirmware {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct RequestHealth;
+
+impl Message for RequestHealth {
+    type Result = Result<ResponseHealth>;
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseHealth {
+    pub udp: TransportStatus,
+    /// Received datagrams, by source
+    pub decode: DecodeStatus,
+    pub frames: FrameStats,
+    /// Not set if redundancy is disabled
+    pub redundancy: Option<RedundancyStatus>,
+}
+
+#[derive(Debug)]
+pub struct RequestMetrics;
+
+impl Message for RequestMetrics {
+    type Result = Result<ResponseMetrics>;
+}
+
+/// Everything `/metrics` reports, apart from the requests to the web API
+#[derive(Debug)]
+pub struct ResponseMetrics {
+    pub nodes: Vec<NodeMetricsEntry>,
+    pub frames: FrameStats,
+    pub udp: TransportStatus,
+    pub decode: DecodeStatus,
+    pub animations: usize,
+    /// Time to render and send the frames of a tick
+    pub render_time: Histogram,
+    pub redundancy: Option<RedundancyStatus>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FrameStats {
+    pub sent: u64,
+    /// Frames the socket could not send, `udp` in `ResponseHealth` has the errors
+    pub dropped: u64,
+    /// `dropped` as a fraction of all frames that were rendered to be sent
+    pub drop_rate: f64,
+    /// Frames that were the same as the previous one, and not sent because of that
+    pub skipped: u64,
+    /// The size of the skipped ArtDmx packets
+    pub bytes_saved: u64,
+    /// Ticks that sent frames, every tick sends its frames in one batch
+    pub batches: u64,
+    /// Seconds spent encoding and sending the frames of a tick
+    pub last_batch_time: f64,
+    pub average_batch_time: f64,
+    pub max_batch_time: f64,
+}
+
+#[derive(Debug)]
+pub struct StartRecording;
+
+impl Message for StartRecording {
+    type Result = Result<RecordingStatus>;
+}
+
+#[derive(Debug)]
+pub struct StopRecording;
+
+impl Message for StopRecording {
+    type Result = Result<RecordingStatus>;
+}
+
+#[derive(Debug)]
+pub struct RequestRecording;
+
+impl Message for RequestRecording {
+    type Result = Result<RecordingStatus>;
+}
+
+#[derive(Debug)]
+pub struct RequestTimeline;
+
+impl Message for RequestTimeline {
+    type Result = Result<TimelineStatus>;
+}
+
+#[derive(Debug)]
+pub struct SetTimecodeLock {
+    pub locked: bool,
+}
+
+impl Message for SetTimecodeLock {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct SetNodeExternal {
+    pub ip: String,
+    pub universe: u16,
+}
+
+impl Message for SetNodeExternal {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct SetGroupBrightness {
+    pub group: String,
+    pub brightness: u8,
+}
+
+impl Message for SetGroupBrightness {
+    type Result = Result<()>;
+}
+
+#[derive(Debug)]
+pub struct RecallScene {
+    pub name: String,
+}
+
+impl Message for RecallScene {
+    type Result = Result<()>;
+}
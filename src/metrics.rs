@@ -0,0 +1,284 @@
+use crate::artnet::LinkState;
+use crate::messages::ResponseMetrics;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Upper bounds in seconds of the histogram buckets, from well within a render tick to far beyond it
+const BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.,
+];
+
+/// A Prometheus histogram of durations in seconds
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    /// The amount of observations per bucket of `BUCKETS`, the ones above the last are only in `count`
+    buckets: [u64; 10],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, seconds: f64) {
+        if let Some(index) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Requests to the web API, by route pattern like `/api/set_color/{ip}/{color}`
+#[derive(Default)]
+pub struct ApiMetrics {
+    /// Keyed by method, route and status code
+    requests: BTreeMap<(String, String, u16), u64>,
+    durations: BTreeMap<String, Histogram>,
+}
+
+impl ApiMetrics {
+    pub fn record(&mut self, method: &str, route: String, status: u16, seconds: f64) {
+        self.durations
+            .entry(route.clone())
+            .or_default()
+            .observe(seconds);
+        *self
+            .requests
+            .entry((method.to_owned(), route, status))
+            .or_default() += 1;
+    }
+}
+
+/// Renders everything in the Prometheus text format
+pub fn render(metrics: &ResponseMetrics, api: &ApiMetrics) -> String {
+    let mut out = Exposition::default();
+
+    let online = metrics.nodes.iter().filter(|n| n.online).count();
+    out.header("nodes", "gauge", "Torches that were seen, by state");
+    out.sample("nodes", &[("state", "online")], online as f64);
+    out.sample(
+        "nodes",
+        &[("state", "offline")],
+        (metrics.nodes.len() - online) as f64,
+    );
+    out.header("node_online", "gauge", "Whether a torch replies to ArtPoll");
+    for node in &metrics.nodes {
+        let online = if node.online { 1. } else { 0. };
+        out.sample("node_online", &[("ip", &node.ip)], online);
+    }
+    out.header(
+        "node_rtt_seconds",
+        "gauge",
+        "Round-trip time of the last ArtPoll to PollReply",
+    );
+    for node in &metrics.nodes {
+        if let Some(rtt) = node.metrics.rtt {
+            out.sample("node_rtt_seconds", &[("ip", &node.ip)], rtt);
+        }
+    }
+    out.header(
+        "node_missed_poll_replies_total",
+        "counter",
+        "ArtPolls a torch did not reply to",
+    );
+    for node in &metrics.nodes {
+        let missed = node.metrics.missed_poll_replies as f64;
+        out.sample(
+            "node_missed_poll_replies_total",
+            &[("ip", &node.ip)],
+            missed,
+        );
+    }
+    out.header(
+        "node_frames_dropped_total",
+        "counter",
+        "Frames to a torch that could not be sent",
+    );
+    for node in &metrics.nodes {
+        let dropped = node.metrics.frames_dropped as f64;
+        out.sample("node_frames_dropped_total", &[("ip", &node.ip)], dropped);
+    }
+
+    let frames = &metrics.frames;
+    out.counter("frames_sent_total", "Frames sent", frames.sent);
+    out.counter(
+        "frames_dropped_total",
+        "Frames the socket could not send",
+        frames.dropped,
+    );
+    out.counter(
+        "frames_skipped_total",
+        "Frames not sent because they did not change",
+        frames.skipped,
+    );
+    out.header(
+        "render_tick_seconds",
+        "histogram",
+        "Time to render and send the frames of a tick",
+    );
+    out.histogram("render_tick_seconds", &[], &metrics.render_time);
+    out.header("animations", "gauge", "Loaded animations");
+    out.sample("animations", &[], metrics.animations as f64);
+
+    let udp = &metrics.udp;
+    out.header("udp_up", "gauge", "Whether the Art-Net socket is bound");
+    out.sample(
+        "udp_up",
+        &[],
+        if udp.state == LinkState::Down { 0. } else { 1. },
+    );
+    out.counter("udp_packets_sent_total", "Packets sent", udp.sent);
+    out.counter("udp_send_errors_total", "Failed sends", udp.send_errors);
+    out.counter(
+        "udp_dropped_packets_total",
+        "Packets given up on",
+        udp.dropped,
+    );
+    out.counter(
+        "udp_receive_errors_total",
+        "Failed receives",
+        udp.receive_errors,
+    );
+    out.counter(
+        "udp_socket_rebuilds_total",
+        "Times the socket was rebuilt after failing sends",
+        udp.rebuilds,
+    );
+    out.header(
+        "udp_received_packets_total",
+        "counter",
+        "Received datagrams, by how they decoded",
+    );
+    let decode = &metrics.decode;
+    for (result, count) in &[
+        ("decoded", decode.decoded),
+        ("unknown_opcode", decode.unknown_opcode),
+        ("malformed", decode.malformed),
+    ] {
+        let count = *count as f64;
+        out.sample("udp_received_packets_total", &[("result", result)], count);
+    }
+
+    if let Some(redundancy) = &metrics.redundancy {
+        let role = format!("{:?}", redundancy.role).to_lowercase();
+        out.header(
+            "redundancy_active",
+            "gauge",
+            "Whether this instance of a redundant pair sends to the torches",
+        );
+        let active = if redundancy.active { 1. } else { 0. };
+        out.sample("redundancy_active", &[("role", &role)], active);
+        out.header(
+            "redundancy_peer_alive",
+            "gauge",
+            "Whether the other instance sent a heartbeat within the timeout",
+        );
+        let alive = if redundancy.peer_alive { 1. } else { 0. };
+        out.sample("redundancy_peer_alive", &[], alive);
+        out.counter(
+            "redundancy_takeovers_total",
+            "Times the standby took over from the primary",
+            redundancy.takeovers,
+        );
+        out.counter(
+            "redundancy_states_received_total",
+            "Newer states received from the other instance",
+            redundancy.states_received,
+        );
+        out.counter(
+            "redundancy_replication_failures_total",
+            "Attempts to send the state to the other instance that failed",
+            redundancy.replication_failures,
+        );
+    }
+
+    out.header(
+        "http_requests_total",
+        "counter",
+        "Requests to the web API, by route",
+    );
+    for ((method, route, status), count) in &api.requests {
+        let status = status.to_string();
+        let labels = [
+            ("method", method.as_str()),
+            ("route", route),
+            ("status", &status),
+        ];
+        out.sample("http_requests_total", &labels, *count as f64);
+    }
+    out.header(
+        "http_request_duration_seconds",
+        "histogram",
+        "Time to answer a request to the web API, by route",
+    );
+    for (route, histogram) in &api.durations {
+        out.histogram(
+            "http_request_duration_seconds",
+            &[("route", route)],
+            histogram,
+        );
+    }
+    out.text
+}
+
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP reality_lights_{} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE reality_lights_{} {}", name, kind);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, "counter", help);
+        self.sample(name, &[], value as f64);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = writeln!(
+            self.text,
+            "reality_lights_{}{} {}",
+            name,
+            format_labels(labels),
+            value
+        );
+    }
+
+    fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let bucket = format!("{}_bucket", name);
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += count;
+            let bound = bound.to_string();
+            let labels: Vec<_> = labels
+                .iter()
+                .cloned()
+                .chain(Some(("le", &*bound)))
+                .collect();
+            self.sample(&bucket, &labels, cumulative as f64);
+        }
+        let labels_inf: Vec<_> = labels.iter().cloned().chain(Some(("le", "+Inf"))).collect();
+        self.sample(&bucket, &labels_inf, histogram.count as f64);
+        self.sample(&format!("{}_sum", name), labels, histogram.sum);
+        self.sample(&format!("{}_count", name), labels, histogram.count as f64);
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
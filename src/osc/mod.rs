@@ -0,0 +1,185 @@
+mod packet;
+
+pub use self::packet::{OscArgument, OscCodec, OscMessage};
+
+use crate::config::Config;
+use crate::messages::{
+    RecallScene, SetBlackout, SetBpm, SetGroupBrightness, SetMasterBrightness, SetNodeAnimation,
+    SetNodeColor, TapTempo,
+};
+use crate::service::Service;
+use crate::Result;
+use actix::fut::wrap_future;
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, Running, StreamHandler};
+use failure::Error;
+use futures::Future;
+use std::net::SocketAddr;
+use tokio_udp::{UdpFramed, UdpSocket};
+
+/// Listens for OSC messages and forwards them to the service.
+///
+/// Supported addresses:
+/// - `/torch/{ip}/animation name`
+/// - `/torch/{ip}/color r g b`, either integers from 0 to 255 or floats from 0 to 1
+/// - `/group/{name}/brightness value`, an integer from 0 to 100 or a float from 0 to 1
+/// - `/master/brightness value`, same as the group brightness
+/// - `/scene/{name}`
+/// - `/blackout 1` and `/blackout 0`
+/// - `/tempo/tap` and `/tempo/bpm bpm`
+pub struct OscServer {
+    service: Addr<Service>,
+}
+
+impl Actor for OscServer {
+    type Context = Context<Self>;
+}
+
+impl StreamHandler<(Vec<OscMessage>, SocketAddr), Error> for OscServer {
+    fn handle(&mut self, (messages, addr): (Vec<OscMessage>, SocketAddr), ctx: &mut Context<Self>) {
+        for message in messages {
+            if let Err(e) = self.dispatch(&message, ctx) {
+                println!("Invalid OSC message {:?} from {}: {:?}", message, addr, e);
+            }
+        }
+    }
+
+    /// A failed receive would stop the stream, and with it the server
+    fn error(&mut self, error: Error, _ctx: &mut Context<Self>) -> Running {
+        println!("Could not receive OSC message: {:?}", error);
+        Running::Continue
+    }
+}
+
+pub fn run(addr: &Addr<Service>) -> Result<Option<Addr<OscServer>>> {
+    let config = Config::from_file("config.json")?;
+    let endpoint = match config.osc_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+    let socket = UdpSocket::bind(&endpoint)?;
+    let service = addr.clone();
+    let server = OscServer::create(move |ctx| {
+        OscServer::add_stream(
+            UdpFramed::with_decode(socket, OscCodec::default(), true),
+            ctx,
+        );
+        OscServer { service }
+    });
+    println!("OSC server running on {}", endpoint);
+    Ok(Some(server))
+}
+
+impl OscServer {
+    fn dispatch(&self, message: &OscMessage, ctx: &mut Context<Self>) -> Result<()> {
+        let path: Vec<&str> = message.address.split('/').skip(1).collect();
+        let arguments = &message.arguments;
+        match path.as_slice() {
+            ["torch", ip, "animation"] => {
+                let animation_name = match arguments.first().and_then(OscArgument::as_str) {
+                    Some(name) => name.to_owned(),
+                    None => bail!("Expected an animation name"),
+                };
+                self.send(
+                    ctx,
+                    SetNodeAnimation {
+                        ip: (*ip).to_owned(),
+                        animation_name,
+                    },
+                );
+            }
+            ["torch", ip, "color"] => {
+                let values: Vec<f32> = arguments.iter().filter_map(OscArgument::as_f32).collect();
+                if values.len() != 3 {
+                    bail!("Expected 3 color values");
+                }
+                let is_float = arguments.iter().any(|a| matches!(a, OscArgument::Float(_)));
+                let channel = |v: f32| {
+                    let v = if is_float { v * 255. } else { v };
+                    v.clamp(0., 255.).round() as u8
+                };
+                self.send(
+                    ctx,
+                    SetNodeColor {
+                        ip: (*ip).to_owned(),
+                        color_name: format!(
+                            "{:02X}{:02X}{:02X}",
+                            channel(values[0]),
+                            channel(values[1]),
+                            channel(values[2])
+                        ),
+                    },
+                );
+            }
+            ["group", group, "brightness"] => {
+                let brightness = percentage(arguments)?;
+                self.send(
+                    ctx,
+                    SetGroupBrightness {
+                        group: (*group).to_owned(),
+                        brightness,
+                    },
+                );
+            }
+            ["master", "brightness"] => {
+                let brightness = percentage(arguments)?;
+                self.send(ctx, SetMasterBrightness { brightness });
+            }
+            ["scene", name] => {
+                if message.is_trigger() {
+                    self.send(
+                        ctx,
+                        RecallScene {
+                            name: (*name).to_owned(),
+                        },
+                    );
+                }
+            }
+            ["blackout"] => {
+                let latched = match arguments.first().and_then(OscArgument::as_f32) {
+                    Some(v) => v != 0.,
+                    None => bail!("Expected 1 to latch or 0 to release"),
+                };
+                self.send(ctx, SetBlackout { latched });
+            }
+            ["tempo", "tap"] => {
+                if message.is_trigger() {
+                    self.send(ctx, TapTempo);
+                }
+            }
+            ["tempo", "bpm"] => {
+                let bpm = match arguments.first().and_then(OscArgument::as_f32) {
+                    Some(bpm) => f64::from(bpm),
+                    None => bail!("Expected a BPM"),
+                };
+                self.send(ctx, SetBpm { bpm });
+            }
+            _ => bail!("Unknown address"),
+        }
+        Ok(())
+    }
+
+    fn send<M, T>(&self, ctx: &mut Context<Self>, message: M)
+    where
+        M: Message<Result = Result<T>> + Send + 'static,
+        T: Send + 'static,
+        Service: Handler<M>,
+    {
+        ctx.spawn(wrap_future(self.service.send(message).then(|result| {
+            match result {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => println!("OSC command failed: {:?}", e),
+                Err(e) => println!("OSC command failed: {:?}", e),
+            }
+            Ok(())
+        })));
+    }
+}
+
+/// Floats are expected to be between 0 and 1, integers between 0 and 100
+fn percentage(arguments: &[OscArgument]) -> Result<u8> {
+    Ok(match arguments.first() {
+        Some(OscArgument::Float(f)) => (f * 100.).clamp(0., 100.).round() as u8,
+        Some(OscArgument::Int(i)) => (*i).clamp(0, 100) as u8,
+        _ => bail!("Expected a brightness"),
+    })
+}
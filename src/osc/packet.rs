@@ -0,0 +1,163 @@
+use crate::Result;
+use bytes::BytesMut;
+use failure::Error;
+use tokio_codec::Decoder;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArgument {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+}
+
+impl OscArgument {
+    /// Numbers are returned as is, `true` as 1 and `false` as 0
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            OscArgument::Int(i) => Some(*i as f32),
+            OscArgument::Float(f) => Some(*f),
+            OscArgument::Bool(b) => Some(if *b { 1. } else { 0. }),
+            OscArgument::String(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            OscArgument::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub arguments: Vec<OscArgument>,
+}
+
+impl OscMessage {
+    /// Buttons send 1 when pressed and 0 when released, only the press should trigger an action
+    pub fn is_trigger(&self) -> bool {
+        self.arguments
+            .first()
+            .and_then(OscArgument::as_f32)
+            .map(|v| v != 0.)
+            .unwrap_or(true)
+    }
+}
+
+/// Decodes an OSC packet, flattening bundles into the messages they contain
+pub fn decode(bytes: &[u8], messages: &mut Vec<OscMessage>) -> Result<()> {
+    let mut reader = Reader { bytes };
+    if bytes.starts_with(b"#bundle\0") {
+        reader.string()?;
+        // The time tag is ignored, every message is executed immediately
+        reader.take(8)?;
+        while !reader.bytes.is_empty() {
+            let size = reader.i32()?;
+            if size < 0 {
+                bail!("Invalid bundle element size {}", size);
+            }
+            decode(reader.take(size as usize)?, messages)?;
+        }
+        return Ok(());
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        bail!("Invalid OSC address {:?}", address);
+    }
+    let mut arguments = Vec::new();
+    // Some very old implementations leave out the type tags, these messages are treated as having no arguments
+    if !reader.bytes.is_empty() {
+        let tags = reader.string()?;
+        if !tags.starts_with(',') {
+            bail!("Invalid OSC type tags {:?}", tags);
+        }
+        for tag in tags.chars().skip(1) {
+            arguments.push(match tag {
+                'i' => OscArgument::Int(reader.i32()?),
+                'f' => OscArgument::Float(f32::from_bits(reader.i32()? as u32)),
+                's' | 'S' => OscArgument::String(reader.string()?),
+                'T' => OscArgument::Bool(true),
+                'F' => OscArgument::Bool(false),
+                'N' | 'I' => continue,
+                'h' | 't' | 'd' => {
+                    reader.take(8)?;
+                    continue;
+                }
+                'c' | 'r' | 'm' => {
+                    reader.take(4)?;
+                    continue;
+                }
+                'b' => {
+                    let size = reader.i32()?.max(0) as usize;
+                    reader.take(size.div_ceil(4) * 4)?;
+                    continue;
+                }
+                tag => bail!("Unsupported OSC type tag {:?}", tag),
+            });
+        }
+    }
+    messages.push(OscMessage { address, arguments });
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            bail!("OSC packet too short");
+        }
+        let (result, remaining) = self.bytes.split_at(length);
+        self.bytes = remaining;
+        Ok(result)
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Strings are null terminated and padded to a multiple of 4 bytes
+    fn string(&mut self) -> Result<String> {
+        let length = match self.bytes.iter().position(|b| *b == 0) {
+            Some(length) => length,
+            None => bail!("OSC string is not terminated"),
+        };
+        let padded = (length + 4) / 4 * 4;
+        let bytes = self.take(padded.min(self.bytes.len()))?;
+        Ok(String::from_utf8(bytes[..length].to_vec())?)
+    }
+}
+
+/// Decodes every datagram into the messages it contains.
+///
+/// Invalid packets are logged and skipped, so one bad packet does not end the stream.
+#[derive(Default)]
+pub struct OscCodec {}
+
+impl Decoder for OscCodec {
+    type Item = Vec<OscMessage>;
+    type Error = Error;
+
+    fn decode(&mut self, bytes: &mut BytesMut) -> Result<Option<Self::Item>> {
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        let mut messages = Vec::new();
+        let result = decode(bytes, &mut messages);
+        bytes.clear();
+        match result {
+            Ok(()) => Ok(Some(messages)),
+            Err(e) => {
+                println!("Could not decode OSC packet: {:?}", e);
+                Ok(None)
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::{FlashLimiterConfig, FlashLimiterMode};
+use crate::messages::AnimationFrame;
+use std::collections::{HashMap, VecDeque};
+
+/// Limits how often the luminance of a torch, or of the whole installation, can change drastically.
+///
+/// A flash is a pair of opposing transitions of at least `min_transition` luminance.
+/// When a torch, or the installation as a whole, already had `max_flashes_per_second` flashes in the last second,
+/// any further transitions are smoothed or suppressed depending on the configured mode.
+pub struct FlashLimiter {
+    pub config: FlashLimiterConfig,
+    torches: HashMap<String, TorchState>,
+    installation: Transitions,
+    /// The luminance of the torches rendered since the last `end_tick`
+    rendered: Vec<f32>,
+    installation_limited_until: f64,
+    last_log: f64,
+}
+
+struct TorchState {
+    last_frame: AnimationFrame,
+    transitions: Transitions,
+    violations: u64,
+}
+
+struct Transitions {
+    luminance: f32,
+    times: VecDeque<f64>,
+}
+
+impl Transitions {
+    fn new(luminance: f32) -> Transitions {
+        Transitions {
+            luminance,
+            times: VecDeque::new(),
+        }
+    }
+
+    fn is_transition(&self, luminance: f32, config: &FlashLimiterConfig) -> bool {
+        (luminance - self.luminance).abs() >= f32::from(config.min_transition)
+    }
+
+    fn is_full(&mut self, now: f64, config: &FlashLimiterConfig) -> bool {
+        while let Some(time) = self.times.front() {
+            if now - time < 1. {
+                break;
+            }
+            self.times.pop_front();
+        }
+        self.times.len() >= usize::from(config.max_flashes_per_second) * 2
+    }
+
+    fn record(&mut self, luminance: f32, now: f64, config: &FlashLimiterConfig) {
+        if self.is_transition(luminance, config) {
+            self.times.push_back(now);
+        }
+        self.luminance = luminance;
+    }
+}
+
+impl FlashLimiter {
+    pub fn new(config: FlashLimiterConfig) -> FlashLimiter {
+        FlashLimiter {
+            config,
+            torches: HashMap::new(),
+            installation: Transitions::new(0.),
+            rendered: Vec::new(),
+            installation_limited_until: 0.,
+            last_log: 0.,
+        }
+    }
+
+    pub fn apply(&mut self, ip: &str, frame: &mut AnimationFrame, now: f64) {
+        if !self.config.enabled {
+            return;
+        }
+        let installation_limited = now < self.installation_limited_until;
+        let config = &self.config;
+        let state = self
+            .torches
+            .entry(ip.to_owned())
+            .or_insert_with(|| TorchState {
+                last_frame: *frame,
+                transitions: Transitions::new(luminance(frame)),
+                violations: 0,
+            });
+
+        let new_luminance = luminance(frame);
+        if state.transitions.is_transition(new_luminance, config)
+            && (installation_limited || state.transitions.is_full(now, config))
+        {
+            state.violations += 1;
+            match config.mode {
+                FlashLimiterMode::Suppress => *frame = state.last_frame,
+                FlashLimiterMode::Smooth => {
+                    let delta = (new_luminance - state.transitions.luminance).abs();
+                    let factor = ((f32::from(config.min_transition) - 1.) / delta).max(0.);
+                    blend(frame, &state.last_frame, factor);
+                }
+            }
+            if now - self.last_log > 10. {
+                println!(
+                    "Flash limiter: torch {} exceeds {} flashes per second ({} violations)",
+                    ip, config.max_flashes_per_second, state.violations
+                );
+                self.last_log = now;
+            }
+        }
+        state.transitions.record(luminance(frame), now, config);
+        state.last_frame = *frame;
+        self.rendered.push(state.transitions.luminance);
+    }
+
+    /// Checks the luminance of the installation as a whole, should be called after every torch is
+    /// rendered. Only the torches rendered this tick count, so offline torches do not hide a flash.
+    pub fn end_tick(&mut self, now: f64) {
+        if !self.config.enabled || self.rendered.is_empty() {
+            self.rendered.clear();
+            return;
+        }
+        let luminance = self.rendered.iter().sum::<f32>() / self.rendered.len() as f32;
+        self.rendered.clear();
+        if self.installation.is_transition(luminance, &self.config)
+            && self.installation.is_full(now, &self.config)
+        {
+            if now >= self.installation_limited_until {
+                println!(
+                    "Flash limiter: installation exceeds {} flashes per second, limiting all torches",
+                    self.config.max_flashes_per_second
+                );
+            }
+            self.installation_limited_until = now + 1.;
+        }
+        self.installation.record(luminance, now, &self.config);
+    }
+
+    pub fn violations(&self, ip: &str) -> u64 {
+        self.torches.get(ip).map(|t| t.violations).unwrap_or(0)
+    }
+}
+
+fn luminance(frame: &AnimationFrame) -> f32 {
+    let total: f32 = frame
+        .iter()
+        .flatten()
+        .map(|(r, g, b)| 0.2126 * f32::from(*r) + 0.7152 * f32::from(*g) + 0.0722 * f32::from(*b))
+        .sum();
+    total / (22. * 7.)
+}
+
+/// Moves `previous` towards `frame` by `factor`, and stores the result in `frame`
+fn blend(frame: &mut AnimationFrame, previous: &AnimationFrame, factor: f32) {
+    for (pixel, previous) in frame.iter_mut().flatten().zip(previous.iter().flatten()) {
+        let mix = |new: u8, old: u8| {
+            (f32::from(old) + (f32::from(new) - f32::from(old)) * factor).round() as u8
+        };
+        *pixel = (
+            mix(pixel.0, previous.0),
+            mix(pixel.1, previous.1),
+            mix(pixel.2, previous.2),
+        );
+    }
+}
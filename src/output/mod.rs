@@ -0,0 +1,421 @@
+mod flash_limiter;
+
+pub use self::flash_limiter::FlashLimiter;
+
+use crate::config::{BrightnessConfig, CalibrationConfig, CalibrationProfile, FlashLimiterConfig};
+use crate::messages::AnimationFrame;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+type GammaTable = [[u8; 256]; 3];
+
+/// The size of the DMX data sent to a torch
+pub const PAYLOAD_SIZE: usize = 450;
+/// Cached payloads beyond this clear the cache, so brightness changes can not use up memory
+const MAX_CACHED_PAYLOADS: usize = 4096;
+/// The numerator of `limit_brightness` that leaves every channel unchanged
+const FULL_BRIGHTNESS: u32 = 255 * 100 * 100;
+
+/// Identifies the content of a frame before the output stage, so the encoded payload can be shared
+/// by every torch with the same output settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameId {
+    Color(u8, u8, u8),
+    /// The id of a loaded animation and the index of the frame
+    Animation(u64, usize),
+}
+
+/// Everything the output stage does to a frame of a torch, except the flash limiter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct OutputKey {
+    blackout: bool,
+    calibration: Option<usize>,
+    numerator: u32,
+    max_power: Option<u32>,
+}
+
+impl OutputKey {
+    /// Whether frames leave the output stage unchanged
+    fn is_passthrough(&self) -> bool {
+        !self.blackout
+            && self.calibration.is_none()
+            && self.numerator == FULL_BRIGHTNESS
+            && self.max_power.is_none()
+    }
+}
+
+/// The last step before a frame is encoded and sent to a torch.
+///
+/// Every frame goes through here, regardless of whether it came from a color, an animation or anything else.
+pub struct OutputStage {
+    pub brightness: BrightnessConfig,
+    /// When set, every frame is replaced by an all-zero frame
+    pub blackout: bool,
+    /// Dimmer in percent per torch, keyed by IP. Torches that are not listed are at 100%.
+    pub dimmers: HashMap<String, u8>,
+    calibration: CalibrationConfig,
+    gamma_tables: HashMap<String, GammaTable>,
+    /// A number per calibration profile, for the cache keys
+    profile_ids: HashMap<String, usize>,
+    /// Encoded payloads, shared by the torches that show the same frame with the same settings
+    cache: HashMap<(OutputKey, FrameId), Bytes>,
+    pub flash_limiter: FlashLimiter,
+}
+
+impl OutputStage {
+    pub fn new(
+        brightness: BrightnessConfig,
+        calibration: CalibrationConfig,
+        flash_limiter: FlashLimiterConfig,
+    ) -> OutputStage {
+        let gamma_tables = calibration
+            .profiles
+            .iter()
+            .map(|(name, profile)| (name.clone(), gamma_table(profile.gamma)))
+            .collect();
+        let profile_ids = calibration
+            .profiles
+            .keys()
+            .enumerate()
+            .map(|(id, name)| (name.clone(), id))
+            .collect();
+        OutputStage {
+            brightness,
+            blackout: false,
+            dimmers: HashMap::new(),
+            calibration,
+            gamma_tables,
+            profile_ids,
+            cache: HashMap::new(),
+            flash_limiter: FlashLimiter::new(flash_limiter),
+        }
+    }
+
+    pub fn apply(&mut self, ip: &str, frame: &mut AnimationFrame, now: f64) {
+        if self.blackout {
+            *frame = AnimationFrame::default();
+            return;
+        }
+        self.calibrate(ip, frame);
+        self.limit_brightness(ip, frame);
+        self.flash_limiter.apply(ip, frame, now);
+    }
+
+    /// Applies the output stage to a frame and encodes it.
+    ///
+    /// With an `id` the payload is cached, so a frame is only transformed once for all the torches
+    /// with the same settings. `payload` is the frame encoded as is, which is used when the output
+    /// stage would not change it.
+    pub fn encode(
+        &mut self,
+        ip: &str,
+        mut frame: AnimationFrame,
+        id: Option<FrameId>,
+        payload: Option<&Bytes>,
+        now: f64,
+    ) -> Bytes {
+        // The flash limiter depends on the previous frames of the torch, so nothing can be shared
+        let key = match id {
+            Some(id) if !self.flash_limiter.config.enabled => (self.key(ip), id),
+            _ => {
+                self.apply(ip, &mut frame, now);
+                return encode(&frame);
+            }
+        };
+        if let Some(payload) = payload.filter(|_| key.0.is_passthrough()) {
+            return payload.clone();
+        }
+        if let Some(payload) = self.cache.get(&key) {
+            return payload.clone();
+        }
+        self.apply(ip, &mut frame, now);
+        let payload = encode(&frame);
+        if self.cache.len() >= MAX_CACHED_PAYLOADS {
+            self.cache.clear();
+        }
+        self.cache.insert(key, payload.clone());
+        payload
+    }
+
+    fn key(&self, ip: &str) -> OutputKey {
+        let limits = self.brightness.torches.get(ip);
+        OutputKey {
+            blackout: self.blackout,
+            calibration: self
+                .profile_name(ip)
+                .and_then(|name| self.profile_ids.get(name))
+                .copied(),
+            numerator: self.numerator(ip),
+            max_power: limits
+                .and_then(|l| l.max_power)
+                .or(self.brightness.max_power),
+        }
+    }
+
+    /// Should be called after every torch has been rendered
+    pub fn end_tick(&mut self, now: f64) {
+        self.flash_limiter.end_tick(now);
+    }
+
+    pub fn calibration(&self) -> &CalibrationConfig {
+        &self.calibration
+    }
+
+    pub fn calibration_profile(&self, name: &str) -> CalibrationProfile {
+        self.calibration
+            .profiles
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set_calibration_profile(&mut self, name: String, profile: CalibrationProfile) {
+        let id = self.profile_ids.len();
+        self.profile_ids.entry(name.clone()).or_insert(id);
+        self.cache.clear();
+        self.gamma_tables
+            .insert(name.clone(), gamma_table(profile.gamma));
+        self.calibration.profiles.insert(name, profile);
+    }
+
+    pub fn set_torch_calibration(&mut self, ip: String, profile: String) {
+        self.calibration.torches.insert(ip, profile);
+    }
+
+    fn profile_name(&self, ip: &str) -> Option<&String> {
+        self.calibration
+            .torches
+            .get(ip)
+            .or(self.calibration.default.as_ref())
+    }
+
+    fn calibrate(&self, ip: &str, frame: &mut AnimationFrame) {
+        let name = match self.profile_name(ip) {
+            Some(name) => name,
+            None => return,
+        };
+        let (profile, table) = match (
+            self.calibration.profiles.get(name),
+            self.gamma_tables.get(name),
+        ) {
+            (Some(profile), Some(table)) => (profile, table),
+            _ => return,
+        };
+        let [r, g, b] = profile.gains;
+        let matrix = profile
+            .matrix
+            .unwrap_or([[r, 0., 0.], [0., g, 0.], [0., 0., b]]);
+
+        // White balance first, then the gamma curve of each channel
+        for pixel in frame.iter_mut().flatten() {
+            let input = [f32::from(pixel.0), f32::from(pixel.1), f32::from(pixel.2)];
+            let mut output = [0u8; 3];
+            for (channel, row) in matrix.iter().enumerate() {
+                let value = row[0] * input[0] + row[1] * input[1] + row[2] * input[2];
+                output[channel] = table[channel][value.clamp(0., 255.).round() as usize];
+            }
+            *pixel = (output[0], output[1], output[2]);
+        }
+    }
+
+    /// The scale of every channel, over `FULL_BRIGHTNESS`
+    fn numerator(&self, ip: &str) -> u32 {
+        let max_channel = self
+            .brightness
+            .torches
+            .get(ip)
+            .and_then(|l| l.max_channel)
+            .unwrap_or(self.brightness.max_channel);
+        let dimmer = self.dimmers.get(ip).cloned().unwrap_or(100).min(100);
+        u32::from(max_channel) * u32::from(self.brightness.master.min(100)) * u32::from(dimmer)
+    }
+
+    fn limit_brightness(&self, ip: &str, frame: &mut AnimationFrame) {
+        let max_power = self
+            .brightness
+            .torches
+            .get(ip)
+            .and_then(|l| l.max_power)
+            .or(self.brightness.max_power);
+
+        let numerator = self.numerator(ip);
+        let mut power = 0;
+        for (r, g, b) in frame.iter_mut().flatten() {
+            for c in &mut [r, g, b] {
+                **c = scale(**c, numerator, FULL_BRIGHTNESS);
+                power += u32::from(**c);
+            }
+        }
+
+        // Thermal cap: scale the whole frame down so the sum of all channels fits
+        if let Some(max_power) = max_power {
+            if power > max_power {
+                for (r, g, b) in frame.iter_mut().flatten() {
+                    for c in &mut [r, g, b] {
+                        **c = scale(**c, max_power, power);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The DMX data of a frame sent to a torch, the first 4 pixels are not sent
+pub fn encode(frame: &AnimationFrame) -> Bytes {
+    let mut data = Vec::with_capacity(PAYLOAD_SIZE);
+    for (r, g, b) in frame.iter().flatten().skip(4) {
+        data.extend_from_slice(&[*r, *g, *b]);
+    }
+    Bytes::from(data)
+}
+
+fn scale(value: u8, numerator: u32, denominator: u32) -> u8 {
+    (u32::from(value) * numerator / denominator) as u8
+}
+
+fn gamma_table(gamma: [f32; 3]) -> GammaTable {
+    let mut table = [[0u8; 256]; 3];
+    for (channel, gamma) in gamma.iter().enumerate() {
+        for (value, entry) in table[channel].iter_mut().enumerate() {
+            *entry = (255. * (value as f32 / 255.).powf(*gamma)).round() as u8;
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TorchLimits;
+
+    const PIXELS: u32 = 22 * 7;
+
+    fn stage(brightness: BrightnessConfig) -> OutputStage {
+        OutputStage::new(
+            brightness,
+            CalibrationConfig::default(),
+            FlashLimiterConfig::default(),
+        )
+    }
+
+    fn full_brightness() -> BrightnessConfig {
+        BrightnessConfig {
+            max_channel: 255,
+            ..BrightnessConfig::default()
+        }
+    }
+
+    fn output(stage: &mut OutputStage, ip: &str, pixel: (u8, u8, u8)) -> (u8, u8, u8) {
+        let mut frame = [[pixel; 7]; 22];
+        stage.apply(ip, &mut frame, 0.);
+        assert!(frame.iter().flatten().all(|p| *p == frame[0][0]));
+        frame[0][0]
+    }
+
+    #[test]
+    fn scales_by_max_channel_master_and_dimmer() {
+        let mut torches = HashMap::new();
+        torches.insert(
+            String::from("10.0.0.2"),
+            TorchLimits {
+                max_channel: Some(200),
+                max_power: None,
+            },
+        );
+        let mut stage = stage(BrightnessConfig {
+            torches,
+            ..BrightnessConfig::default()
+        });
+        assert_eq!(output(&mut stage, "10.0.0.1", (255, 51, 0)), (100, 20, 0));
+        assert_eq!(output(&mut stage, "10.0.0.2", (255, 51, 0)), (200, 40, 0));
+
+        stage.brightness.master = 50;
+        stage.dimmers.insert(String::from("10.0.0.1"), 50);
+        assert_eq!(output(&mut stage, "10.0.0.1", (255, 51, 0)), (25, 5, 0));
+        assert_eq!(output(&mut stage, "10.0.0.2", (255, 51, 0)), (100, 20, 0));
+
+        stage.blackout = true;
+        assert_eq!(output(&mut stage, "10.0.0.2", (255, 51, 0)), (0, 0, 0));
+    }
+
+    #[test]
+    fn caps_the_power_of_a_frame() {
+        let mut torches = HashMap::new();
+        torches.insert(
+            String::from("10.0.0.2"),
+            TorchLimits {
+                max_channel: None,
+                max_power: Some(PIXELS * 3 * 51),
+            },
+        );
+        let mut stage = stage(BrightnessConfig {
+            max_power: Some(PIXELS * 3 * 100),
+            torches,
+            ..full_brightness()
+        });
+        assert_eq!(
+            output(&mut stage, "10.0.0.1", (255, 255, 255)),
+            (100, 100, 100)
+        );
+        assert_eq!(
+            output(&mut stage, "10.0.0.2", (255, 255, 255)),
+            (51, 51, 51)
+        );
+        // Frames below the cap are left as they are
+        assert_eq!(
+            output(&mut stage, "10.0.0.1", (90, 100, 110)),
+            (90, 100, 110)
+        );
+    }
+
+    #[test]
+    fn calibrates_per_torch() {
+        let mut stage = stage(full_brightness());
+        stage.set_calibration_profile(
+            String::from("warm"),
+            CalibrationProfile {
+                gains: [1., 0.5, 0.],
+                ..CalibrationProfile::default()
+            },
+        );
+        stage.set_calibration_profile(
+            String::from("gamma"),
+            CalibrationProfile {
+                gamma: [2.; 3],
+                ..CalibrationProfile::default()
+            },
+        );
+        stage.set_torch_calibration(String::from("10.0.0.1"), String::from("warm"));
+        stage.set_torch_calibration(String::from("10.0.0.2"), String::from("gamma"));
+        assert_eq!(
+            output(&mut stage, "10.0.0.1", (200, 200, 200)),
+            (200, 100, 0)
+        );
+        assert_eq!(output(&mut stage, "10.0.0.2", (255, 128, 0)), (255, 64, 0));
+        // Torches without a profile are not calibrated, unless there is a default
+        assert_eq!(
+            output(&mut stage, "10.0.0.3", (200, 200, 200)),
+            (200, 200, 200)
+        );
+        stage.calibration.default = Some(String::from("warm"));
+        assert_eq!(
+            output(&mut stage, "10.0.0.3", (200, 200, 200)),
+            (200, 100, 0)
+        );
+    }
+
+    #[test]
+    fn shares_payloads_between_torches_with_the_same_settings() {
+        let mut stage = stage(BrightnessConfig::default());
+        stage.dimmers.insert(String::from("10.0.0.3"), 50);
+        let frame = [[(255, 255, 255); 7]; 22];
+        let id = Some(FrameId::Color(255, 255, 255));
+        let first = stage.encode("10.0.0.1", frame, id, None, 0.);
+        let second = stage.encode("10.0.0.2", frame, id, None, 0.);
+        let dimmed = stage.encode("10.0.0.3", frame, id, None, 0.);
+        assert_eq!(first.len(), PAYLOAD_SIZE);
+        assert!(first.iter().all(|c| *c == 100));
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        assert!(dimmed.iter().all(|c| *c == 50));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::artnet::rdm::{
+    RdmMessage, Uid, GET_COMMAND_RESPONSE, PID_DEVICE_INFO, PID_DEVICE_LABEL,
+    PID_DEVICE_MODEL_DESCRIPTION, PID_LAMP_HOURS, PID_SENSOR_DEFINITION, PID_SENSOR_VALUE,
+    RESPONSE_ACK,
+};
+use crate::artnet::{Packet, Rdm, TodData, TodRequest};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+
+/// Our own UID, in the range of manufacturer IDs reserved for prototypes
+const CONTROLLER_UID: Uid = Uid([0x7F, 0xF0, 0x00, 0x00, 0x00, 0x01]);
+/// Sensor type of temperature sensors in SENSOR_DEFINITION
+const SENSOR_TEMPERATURE: u8 = 0x00;
+/// Sensors beyond this are not looked at for a temperature sensor
+const MAX_SENSORS: u8 = 8;
+
+/// Discovers the RDM devices behind every node with ArtTodRequest and reads their parameters
+/// with ArtRdm
+#[derive(Default)]
+pub struct RdmController {
+    transaction: u8,
+    nodes: HashMap<SocketAddr, BTreeMap<Uid, Device>>,
+}
+
+struct Device {
+    info: RdmDevice,
+    temperature_sensor: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RdmDevice {
+    pub uid: String,
+    pub label: Option<String>,
+    pub model: Option<String>,
+    pub model_id: Option<u16>,
+    pub dmx_start_address: Option<u16>,
+    pub dmx_footprint: Option<u16>,
+    pub lamp_hours: Option<u32>,
+    /// In degrees Celsius
+    pub temperature: Option<i16>,
+}
+
+impl RdmController {
+    /// The ArtTodRequest for the devices on a universe
+    pub fn discover(universe: u16) -> Packet {
+        Packet::TodRequest(TodRequest {
+            net: (universe >> 8) as u8 & 0x7F,
+            addresses: vec![universe as u8],
+        })
+    }
+
+    /// Takes over the devices of a node and returns the requests for their parameters
+    pub fn tod_data(&mut self, addr: SocketAddr, tod: &TodData) -> Vec<Packet> {
+        let devices = self.nodes.entry(addr).or_default();
+        // Devices that disappeared can only be told apart when the whole table fits in one packet
+        if tod.block_count == 0 && usize::from(tod.uid_total) == tod.uids.len() {
+            devices.retain(|uid, _| tod.uids.contains(uid));
+        }
+        let mut requests = Vec::new();
+        for uid in &tod.uids {
+            let device = devices.entry(*uid).or_insert_with(|| {
+                println!("Found RDM device {} behind {}", uid, addr);
+                Device::new(*uid)
+            });
+            requests.push((*uid, PID_DEVICE_INFO, vec![]));
+            requests.push((*uid, PID_DEVICE_LABEL, vec![]));
+            requests.push((*uid, PID_DEVICE_MODEL_DESCRIPTION, vec![]));
+            requests.push((*uid, PID_LAMP_HOURS, vec![]));
+            if let Some(sensor) = device.temperature_sensor {
+                requests.push((*uid, PID_SENSOR_VALUE, vec![sensor]));
+            }
+        }
+        requests
+            .into_iter()
+            .map(|(uid, pid, data)| self.transaction_packet(tod.net, tod.address, uid, pid, data))
+            .collect()
+    }
+
+    /// Stores the parameter in an RDM response, and returns follow-up requests
+    pub fn response(&mut self, addr: SocketAddr, rdm: &Rdm) -> Vec<Packet> {
+        let message = &rdm.message;
+        if message.command_class != GET_COMMAND_RESPONSE || message.destination != CONTROLLER_UID {
+            return Vec::new();
+        }
+        let device = match self
+            .nodes
+            .get_mut(&addr)
+            .and_then(|devices| devices.get_mut(&message.source))
+        {
+            Some(device) => device,
+            None => return Vec::new(),
+        };
+        if message.port_or_response != RESPONSE_ACK {
+            // Not supported by the device, or it needs more time which the next refresh gives it
+            return Vec::new();
+        }
+        let data = &message.data;
+        let mut follow_up = Vec::new();
+        match message.pid {
+            PID_DEVICE_INFO if data.len() >= 19 => {
+                let info = &mut device.info;
+                info.model_id = Some(u16::from_be_bytes([data[2], data[3]]));
+                info.dmx_footprint = Some(u16::from_be_bytes([data[10], data[11]]));
+                info.dmx_start_address = Some(u16::from_be_bytes([data[14], data[15]]));
+                if device.temperature_sensor.is_none() {
+                    for sensor in 0..data[18].min(MAX_SENSORS) {
+                        follow_up.push((PID_SENSOR_DEFINITION, vec![sensor]));
+                    }
+                }
+            }
+            PID_DEVICE_LABEL => device.info.label = Some(read_string(data)),
+            PID_DEVICE_MODEL_DESCRIPTION => device.info.model = Some(read_string(data)),
+            PID_LAMP_HOURS if data.len() >= 4 => {
+                device.info.lamp_hours =
+                    Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+            }
+            PID_SENSOR_DEFINITION
+                if data.len() >= 2
+                    && data[1] == SENSOR_TEMPERATURE
+                    && device.temperature_sensor.is_none() =>
+            {
+                device.temperature_sensor = Some(data[0]);
+                follow_up.push((PID_SENSOR_VALUE, vec![data[0]]));
+            }
+            PID_SENSOR_VALUE if data.len() >= 3 && Some(data[0]) == device.temperature_sensor => {
+                device.info.temperature = Some(i16::from_be_bytes([data[1], data[2]]));
+            }
+            _ => {}
+        }
+        let source = message.source;
+        follow_up
+            .into_iter()
+            .map(|(pid, data)| self.transaction_packet(rdm.net, rdm.address, source, pid, data))
+            .collect()
+    }
+
+    /// Forgets the devices behind a torch that went offline or moved to another IP
+    pub fn forget(&mut self, addr: &SocketAddr) {
+        self.nodes.remove(addr);
+    }
+
+    pub fn devices(&self, addr: &SocketAddr) -> Vec<RdmDevice> {
+        match self.nodes.get(addr) {
+            Some(devices) => devices.values().map(|d| d.info.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn transaction_packet(
+        &mut self,
+        net: u8,
+        address: u8,
+        destination: Uid,
+        pid: u16,
+        data: Vec<u8>,
+    ) -> Packet {
+        self.transaction = self.transaction.wrapping_add(1);
+        let message = RdmMessage::get(CONTROLLER_UID, destination, self.transaction, pid, data);
+        Packet::Rdm(Rdm {
+            net,
+            address,
+            message,
+        })
+    }
+}
+
+impl Device {
+    fn new(uid: Uid) -> Device {
+        Device {
+            info: RdmDevice {
+                uid: uid.to_string(),
+                label: None,
+                model: None,
+                model_id: None,
+                dmx_start_address: None,
+                dmx_footprint: None,
+                lamp_hours: None,
+                temperature: None,
+            },
+            temperature_sensor: None,
+        }
+    }
+}
+
+fn read_string(data: &[u8]) -> String {
+    let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..len]).into_owned()
+}
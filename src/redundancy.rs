@@ -0,0 +1,312 @@
+use crate::config::{RedundancyConfig, Role};
+use crate::messages::{PeerHeartbeat, PeerState};
+use crate::state::{State, Version};
+use crate::Result;
+use actix::Recipient;
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Heartbeats only hold the role and version, the state is sent over TCP
+const MAX_HEARTBEAT_SIZE: usize = 1024;
+/// Seconds to connect to the other instance, and to send or receive the state
+const STATE_TIMEOUT: u64 = 2;
+
+/// Sent to the other instance on every tick
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Heartbeat {
+    pub role: Role,
+    /// Whether the sender is sending to the torches
+    pub active: bool,
+    /// `State::version` of the sender
+    pub version: Version,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RedundancyStatus {
+    pub role: Role,
+    /// Whether this instance sends to the torches
+    pub active: bool,
+    pub peer: SocketAddr,
+    /// Seconds since the last heartbeat of the other instance
+    pub peer_last_seen: Option<f64>,
+    /// Whether the other instance sent a heartbeat within the timeout
+    pub peer_alive: bool,
+    /// Times the standby took over from the primary
+    pub takeovers: u64,
+    /// Newer states received from the other instance
+    pub states_received: u64,
+    /// Attempts to send the state to the other instance that failed
+    pub replication_failures: u64,
+    /// Why the last attempt failed, cleared when one succeeds
+    pub replication_error: Option<String>,
+}
+
+/// Sending the state to the other instance, which happens on a background thread
+#[derive(Default)]
+struct Replication {
+    /// Set while a state is being sent, so a slow peer does not pile up connections
+    sending: bool,
+    failures: u64,
+    error: Option<String>,
+}
+
+/// Decides whether this instance of a redundant pair sends, and replicates the state between both.
+///
+/// The primary always sends frames. The standby keeps discovering the torches, but sends them no
+/// frames until it did not hear from the primary for `timeout` seconds, and stands by again as
+/// soon as the primary is back. Heartbeats go over UDP and carry the version of the state. While
+/// the other instance reports an older version, the state is sent to it over TCP on the same
+/// address, so the primary also gets the changes that were made while the standby had taken over.
+pub struct Redundancy {
+    config: RedundancyConfig,
+    socket: Option<UdpSocket>,
+    active: bool,
+    /// When the last heartbeat of the other instance was received
+    last_heartbeat: Option<f64>,
+    /// When the primary was last heard from, or when the standby started
+    primary_seen: f64,
+    /// `State::version` of the other instance, unknown until it sent a heartbeat
+    peer_version: Option<Version>,
+    replication: Arc<Mutex<Replication>>,
+    takeovers: u64,
+    states_received: u64,
+}
+
+impl Redundancy {
+    pub fn new(config: RedundancyConfig, now: f64) -> Redundancy {
+        Redundancy {
+            active: config.role == Role::Primary,
+            config,
+            socket: None,
+            last_heartbeat: None,
+            primary_seen: now,
+            peer_version: None,
+            replication: Arc::new(Mutex::new(Replication::default())),
+            takeovers: 0,
+            states_received: 0,
+        }
+    }
+
+    /// Binds the heartbeat socket and the state listener, and forwards what the other instance
+    /// sends from background threads
+    pub fn start(
+        &mut self,
+        heartbeats: Recipient<PeerHeartbeat>,
+        states: Recipient<PeerState>,
+    ) -> Result<()> {
+        let socket = UdpSocket::bind(self.config.listen)?;
+        let listener = TcpListener::bind(self.config.listen)?;
+        let receiver = socket.try_clone()?;
+        let peer = self.config.peer.ip();
+        thread::Builder::new()
+            .name(String::from("redundancy"))
+            .spawn(move || {
+                let mut buffer = vec![0; MAX_HEARTBEAT_SIZE];
+                loop {
+                    let (size, addr) = match receiver.recv_from(&mut buffer) {
+                        Ok(received) => received,
+                        Err(e) => {
+                            println!("Could not receive heartbeat: {:?}", e);
+                            continue;
+                        }
+                    };
+                    if addr.ip() != peer {
+                        println!("Ignoring heartbeat from {}, expected {}", addr, peer);
+                        continue;
+                    }
+                    match serde_json::from_slice(&buffer[..size]) {
+                        Ok(heartbeat) => {
+                            if let Err(e) = heartbeats.do_send(PeerHeartbeat(heartbeat)) {
+                                println!("Could not forward heartbeat: {:?}", e);
+                                return;
+                            }
+                        }
+                        Err(e) => println!("Invalid heartbeat from {}: {:?}", addr, e),
+                    }
+                }
+            })?;
+        thread::Builder::new()
+            .name(String::from("replication"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream
+                        .map_err(Error::from)
+                        .and_then(|stream| receive_state(stream, peer));
+                    match result {
+                        Ok(state) => {
+                            if let Err(e) = states.do_send(PeerState(state)) {
+                                println!("Could not forward state: {:?}", e);
+                                return;
+                            }
+                        }
+                        Err(e) => println!("Could not receive state: {:?}", e),
+                    }
+                }
+            })?;
+        println!(
+            "Running as {:?}, heartbeats on {} with {}",
+            self.config.role, self.config.listen, self.config.peer
+        );
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Records a change of `state`, newer than the last version of the other instance
+    pub fn touch(&self, state: &mut State) {
+        state.touch(
+            self.peer_version.unwrap_or_default(),
+            self.config.role == Role::Primary,
+        );
+    }
+
+    /// Sends a heartbeat, and the state if the other instance has an older one
+    pub fn send_heartbeat(&self, state: &State) {
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return,
+        };
+        let heartbeat = Heartbeat {
+            role: self.config.role,
+            active: self.active,
+            version: state.version,
+        };
+        let result = serde_json::to_vec(&heartbeat)
+            .map_err(Error::from)
+            .and_then(|buffer| Ok(socket.send_to(&buffer, self.config.peer)?));
+        if let Err(e) = result {
+            println!("Could not send heartbeat to {}: {:?}", self.config.peer, e);
+        }
+        if self
+            .peer_version
+            .is_some_and(|version| version < state.version)
+        {
+            self.replicate(state);
+        }
+    }
+
+    /// Sends the state to the other instance from a background thread, unless one is still being sent
+    fn replicate(&self, state: &State) {
+        let mut replication = self.replication.lock().expect("Poisoned");
+        if replication.sending {
+            return;
+        }
+        let peer = self.config.peer;
+        let shared = self.replication.clone();
+        let started = serde_json::to_vec(state)
+            .map_err(Error::from)
+            .and_then(|buffer| {
+                thread::Builder::new()
+                    .name(String::from("replication"))
+                    .spawn(move || {
+                        let result = send_state(peer, &buffer);
+                        shared.lock().expect("Poisoned").finish(peer, result);
+                    })?;
+                Ok(())
+            });
+        match started {
+            Ok(()) => replication.sending = true,
+            Err(e) => replication.finish(peer, Err(e)),
+        }
+    }
+
+    /// Handles a heartbeat of the other instance
+    pub fn heartbeat(&mut self, heartbeat: Heartbeat, now: f64) {
+        if heartbeat.role == self.config.role {
+            println!(
+                "The other instance is also {:?}, check the redundancy config",
+                heartbeat.role
+            );
+        }
+        self.last_heartbeat = Some(now);
+        self.peer_version = Some(heartbeat.version);
+        if heartbeat.role == Role::Primary {
+            self.primary_seen = now;
+            if self.config.role == Role::Standby && self.active {
+                println!("Primary {} is back, standing by", self.config.peer);
+                self.active = false;
+            }
+        }
+    }
+
+    /// Returns the state of the other instance if it is newer than `state`
+    pub fn receive_state(&mut self, peer: State, state: &State) -> Option<State> {
+        if peer.version <= state.version {
+            return None;
+        }
+        self.states_received += 1;
+        Some(peer)
+    }
+
+    /// Takes over when the primary was silent for too long, returns whether it did.
+    /// Without a heartbeat socket the standby can not tell, so it never takes over.
+    pub fn check(&mut self, now: f64) -> bool {
+        if self.active || self.socket.is_none() || now - self.primary_seen < self.config.timeout {
+            return false;
+        }
+        println!(
+            "No heartbeat from primary {} for {} seconds, taking over",
+            self.config.peer, self.config.timeout
+        );
+        self.active = true;
+        self.takeovers += 1;
+        true
+    }
+
+    pub fn status(&self, now: f64) -> RedundancyStatus {
+        let peer_last_seen = self.last_heartbeat.map(|seen| now - seen);
+        let replication = self.replication.lock().expect("Poisoned");
+        RedundancyStatus {
+            role: self.config.role,
+            active: self.active,
+            peer: self.config.peer,
+            peer_last_seen,
+            peer_alive: peer_last_seen.is_some_and(|seen| seen < self.config.timeout),
+            takeovers: self.takeovers,
+            states_received: self.states_received,
+            replication_failures: replication.failures,
+            replication_error: replication.error.clone(),
+        }
+    }
+}
+
+impl Replication {
+    fn finish(&mut self, peer: SocketAddr, result: Result<()>) {
+        self.sending = false;
+        match result {
+            Ok(()) => self.error = None,
+            Err(e) => {
+                println!("Could not send state to {}: {:?}", peer, e);
+                self.failures += 1;
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+fn send_state(peer: SocketAddr, buffer: &[u8]) -> Result<()> {
+    let timeout = Duration::from_secs(STATE_TIMEOUT);
+    let mut stream = TcpStream::connect_timeout(&peer, timeout)?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(buffer)?;
+    Ok(())
+}
+
+fn receive_state(mut stream: TcpStream, peer: IpAddr) -> Result<State> {
+    let addr = stream.peer_addr()?;
+    if addr.ip() != peer {
+        bail!("Ignoring state from {}, expected {}", addr, peer);
+    }
+    stream.set_read_timeout(Some(Duration::from_secs(STATE_TIMEOUT)))?;
+    let mut buffer = Vec::new();
+    stream.read_to_end(&mut buffer)?;
+    Ok(serde_json::from_slice(&buffer)?)
+}
@@ -1,26 +1,44 @@
 use crate::animation_handler::AnimationHandler;
-use crate::artnet::{Client, Codec, RenderMode};
-use crate::config::Config;
+use crate::artnet::{
+    self, Address, AddressCommand, Client, Codec, DecodeStats, Decoded, Dmx, DmxInput, InputAction,
+    IpProg, Packet, PendingChange, RenderMode, Transport, Trigger,
+};
+use crate::audio::{self, AudioState};
+use crate::capture::{CaptureWriter, RecordingStatus};
+use crate::config::{Action, Config, MergeMode};
+use crate::firmware::FirmwareManager;
 use crate::messages::{
-    AddAnimation, RequestAnimationList, RequestNodeList, ResponseAnimationList, ResponseNodeList,
-    SetNodeAnimation, SetNodeColor,
+    AddAnimation, AddFirmware, Animation, AnimationFrame, AudioUpdate, FrameStats,
+    NodeMetricsEntry, PeerHeartbeat, PeerState, PushFirmware, RecallScene, RequestAnimationList,
+    RequestCalibration, RequestFirmware, RequestHealth, RequestMetrics, RequestNodeList,
+    RequestNodeMetrics, RequestRecording, RequestTempo, RequestTimeline, ResponseAnimationList,
+    ResponseHealth, ResponseMetrics, ResponseNodeList, ResponseNodeMetrics, SetBlackout, SetBpm,
+    SetCalibration, SetGroupBrightness, SetMasterBrightness, SetNodeAddress, SetNodeAnimation,
+    SetNodeAudio, SetNodeCalibration, SetNodeColor, SetNodeExternal, SetNodeIp,
+    SetNodeTempoAnimation, SetTimecodeLock, StartRecording, StopRecording, TapTempo,
 };
+use crate::metrics::Histogram;
+use crate::output::{self, FrameId, OutputStage};
+use crate::rdm::RdmController;
+use crate::redundancy::Redundancy;
+use crate::state::{State, Version};
+use crate::tempo::TempoClock;
+use crate::timeline::Timeline;
 use crate::Result;
-use actix::fut::wrap_future;
+use actix::fut::{wrap_stream, ActorStream};
 use actix::{
-    Actor, ActorContext, ArbiterService, AsyncContext, Context, Handler, Message, StreamHandler,
-    Supervised,
+    Actor, Addr, ArbiterService, AsyncContext, Context, Handler, Message, Running, SpawnHandle,
+    StreamHandler, Supervised,
 };
-use artnet_protocol::{ArtCommand, Output};
+use artnet_protocol::ArtCommand;
+use bytes::Bytes;
 use failure::{Error, ResultExt};
 use futures::sync::mpsc::{channel, Sender};
-use futures::{Future, Sink, Stream};
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::io::{Cursor, Read as IoRead, Write as IoWrite};
-use std::net::{SocketAddr, UdpSocket as NetSocket};
+use std::net::{Ipv4Addr, SocketAddr};
 use std::time::Duration;
-use std::{fs, mem};
 use time;
 use tokio_reactor::Handle;
 use tokio_udp::{UdpFramed, UdpSocket};
@@ -30,15 +48,98 @@ pub struct Service {
     config: Config,
     clients: HashMap<SocketAddr, Client>,
     animations: AnimationHandler,
-    udp_sender: Sender<(ArtCommand, SocketAddr)>,
+    output: OutputStage,
+    state: State,
+    input: Option<DmxInput>,
+    /// The last ArtDmx data and the time it was received, by universe
+    external_frames: HashMap<u16, (f64, Vec<u8>)>,
+    audio: AudioState,
+    tempo: TempoClock,
+    timeline: Timeline,
+    firmware: FirmwareManager,
+    rdm: RdmController,
+    last_rdm_discovery: f64,
+    /// Every outgoing packet is written to it while a recording runs
+    recorder: Option<CaptureWriter>,
+    transport: Transport,
+    decode_stats: DecodeStats,
+    frame_stats: FrameStats,
+    render_time: Histogram,
+    /// When the last ArtPoll went out, for the round-trip time of the replies
+    last_poll_sent: Option<f64>,
+    /// Set when running as one of a redundant pair, see `Redundancy`
+    redundancy: Option<Redundancy>,
+    /// The stream of received packets, replaced when the socket is rebuilt
+    udp_stream: Option<SpawnHandle>,
+    udp_sender: Sender<(Packet, SocketAddr)>,
 }
 
+/// Seconds between looking for new RDM devices and refreshing the parameters of known ones
+const RDM_DISCOVERY_INTERVAL: f64 = 30.;
+/// The size of an ArtDmx packet without the DMX data
+const ARTDMX_HEADER_SIZE: usize = 18;
+/// Packets other than frames waiting to be sent, enough for an RDM discovery of every torch
+const QUEUE_SIZE: usize = 4096;
+
 impl Default for Service {
     fn default() -> Service {
+        Service::new(Config::from_file("config.json").expect("Could not load config"))
+    }
+}
+
+impl Service {
+    pub fn new(config: Config) -> Service {
+        let state = State::load(&config.state_file).expect("Could not load state");
+        let mut calibration = config.calibration.clone();
+        calibration
+            .profiles
+            .extend(state.calibration_profiles.clone());
+        calibration
+            .torches
+            .extend(state.calibration_torches.clone());
+        let mut output = OutputStage::new(
+            config.brightness.clone(),
+            calibration,
+            config.flash_limiter.clone(),
+        );
+        if let Some(brightness) = state.master_brightness {
+            output.brightness.master = brightness;
+        }
+        output.blackout = state.blackout;
+        for (group, brightness) in &state.group_brightness {
+            for ip in config.groups.get(group).into_iter().flatten() {
+                output.dimmers.insert(ip.clone(), *brightness);
+            }
+        }
+        let tempo = TempoClock::new(state.bpm.unwrap_or(120.), time::precise_time_s());
+        let input = config.artnet_input.clone().map(DmxInput::new);
+        let timeline = Timeline::new(config.timeline.clone());
+        let redundancy = config
+            .redundancy
+            .clone()
+            .map(|redundancy| Redundancy::new(redundancy, time::precise_time_s()));
         Service {
-            config: Config::from_file("config.json").expect("Could not load config"),
+            config,
             clients: HashMap::new(),
             animations: AnimationHandler::new().expect("Cannot load animation handler"),
+            output,
+            input,
+            external_frames: HashMap::new(),
+            state,
+            audio: AudioState::default(),
+            tempo,
+            timeline,
+            firmware: FirmwareManager::load().expect("Cannot load firmware"),
+            rdm: RdmController::default(),
+            last_rdm_discovery: 0.,
+            recorder: None,
+            transport: Transport::default(),
+            decode_stats: DecodeStats::default(),
+            frame_stats: FrameStats::default(),
+            render_time: Histogram::default(),
+            last_poll_sent: None,
+            redundancy,
+            udp_stream: None,
             udp_sender: channel(0).0,
         }
     }
@@ -52,149 +153,962 @@ impl Supervised for Service {}
 
 impl ArbiterService for Service {
     fn service_started(&mut self, ctx: &mut Context<Self>) {
-        if let Err(e) = self.init(ctx) {
-            println!("Could not start arnet::service: {:?}", e);
-            ctx.stop();
-        }
+        self.init(ctx);
     }
 }
 
-impl StreamHandler<(ArtCommand, SocketAddr), Error> for Service {
-    fn handle(&mut self, (command, addr): (ArtCommand, SocketAddr), _ctx: &mut Context<Self>) {
+impl StreamHandler<(Decoded, SocketAddr), Error> for Service {
+    fn handle(&mut self, (decoded, addr): (Decoded, SocketAddr), _ctx: &mut Context<Self>) {
+        let packet = match self
+            .decode_stats
+            .record(addr, decoded, time::precise_time_s())
+        {
+            Some(packet) => packet,
+            None => return,
+        };
+        let command = match packet {
+            Packet::Command(command) => command,
+            Packet::Trigger(trigger) => {
+                self.handle_trigger(&trigger);
+                return;
+            }
+            Packet::TimeCode(timecode) => {
+                let now = time::precise_time_s();
+                if let Some(action) = self.timeline.update(timecode, now) {
+                    println!("Timecode {} reached a cue", timecode);
+                    if let Err(e) = self.apply_action(action) {
+                        println!("Could not apply cue: {:?}", e);
+                        self.timeline.cue_failed(now);
+                    }
+                }
+                return;
+            }
+            Packet::IpProgReply(reply) => {
+                println!(
+                    "Torch {} reports IP {} mask {}{}",
+                    addr.ip(),
+                    reply.address,
+                    reply.mask,
+                    if reply.dhcp { " (DHCP)" } else { "" }
+                );
+                return;
+            }
+            Packet::FirmwareReply(reply) => {
+                let now = time::precise_time_s();
+                if let Some(block) = self.firmware.reply(&addr, reply, now) {
+                    if let Err(e) = self
+                        .udp_sender
+                        .try_send((Packet::FirmwareMaster(block), addr))
+                    {
+                        println!("Can not send firmware: {:?}", e);
+                    }
+                }
+                return;
+            }
+            Packet::TodData(tod) => {
+                let requests = self.rdm.tod_data(addr, &tod);
+                self.send_all(requests, addr);
+                return;
+            }
+            Packet::Rdm(rdm) => {
+                let requests = self.rdm.response(addr, &rdm);
+                self.send_all(requests, addr);
+                return;
+            }
+            // Sent by other controllers, received ArtDmx is decoded as `ArtCommand::Output`
+            Packet::Dmx(_)
+            | Packet::Address(_)
+            | Packet::IpProg(_)
+            | Packet::FirmwareMaster(_)
+            | Packet::TodRequest(_) => return,
+        };
+        if let Some(input) = &self.input {
+            match &command {
+                ArtCommand::Poll(_) => {
+                    if addr.ip() != input.config.address {
+                        let reply = ArtCommand::PollReply(Box::new(input.poll_reply()));
+                        if let Err(e) = self.udp_sender.try_send((reply.into(), addr)) {
+                            println!("Can not send poll reply: {:?}", e);
+                        }
+                    }
+                    return;
+                }
+                ArtCommand::Output(output) if output.subnet == input.config.universe => {
+                    self.handle_dmx_input(&output.data);
+                    return;
+                }
+                // Our own reply to our own poll
+                ArtCommand::PollReply(reply) if reply.address == input.config.address => return,
+                _ => {}
+            }
+        }
+        if let ArtCommand::Output(output) = &command {
+            let universe = output.subnet;
+            let mapped = self.clients.values().any(|c| match &c.current {
+                RenderMode::External(u, _) => *u == universe,
+                _ => false,
+            });
+            if mapped {
+                if !self.external_frames.contains_key(&universe) {
+                    println!("External stream on universe {} started", universe);
+                }
+                let received = time::precise_time_s();
+                self.external_frames
+                    .insert(universe, (received, output.data.clone()));
+                return;
+            }
+        }
         if !self.clients.contains_key(&addr) {
             if let ArtCommand::PollReply(reply) = &command {
-                let client = match Client::new(addr, reply) {
+                let mut client = match Client::new(addr, reply, self.config.default_mode.clone()) {
                     Ok(c) => c,
                     Err(e) => {
                         println!("Could not accept client: {:?}", e);
                         return;
                     }
                 };
+                // A torch that got a new IP address keeps the mode it had
+                let previous = self
+                    .clients
+                    .iter()
+                    .find(|(_, c)| c.mac().is_some() && c.mac == client.mac)
+                    .map(|(addr, _)| *addr);
+                if let Some(previous) = previous.and_then(|addr| self.clients.remove(&addr)) {
+                    self.rdm.forget(&previous.socket_address);
+                    println!(
+                        "Torch {} moved from {} to {}",
+                        client.mac_string, previous.addr_string, client.addr_string
+                    );
+                    client.current = previous.current;
+                    client.pending = previous.pending;
+                    client.change = previous.change;
+                    client.metrics = previous.metrics;
+                } else if let Some(mode) = self.take_scene_mode(&client.addr_string) {
+                    client.current = mode;
+                    self.state.set_mode(&client);
+                    self.save_state();
+                } else if let Some(mode) = self.state.mode_for(&client) {
+                    client.current = mode;
+                } else {
+                    client.current = self.config.mode_for(&client.addr_string, client.mac());
+                }
+                client.millis_since_last_frame = 1000;
+                let discover = RdmController::discover(client.universe);
+                if let Err(e) = self.udp_sender.try_send((discover, addr)) {
+                    println!("Can not send RDM discovery: {:?}", e);
+                }
                 self.clients.insert(addr, client);
             } else {
                 return;
             }
         }
 
+        let now = time::precise_time_s();
+        let timeout = self.config.client_timeout;
         let client = self.clients.get_mut(&addr).expect("Unreachable");
-        client.last_reply_received = time::precise_time_s();
+        if client.last_reply_received > 0. && !client.is_online(now, timeout) {
+            // The torch probably rebooted and lost its frame, send the assigned mode again
+            println!("Torch {} reappeared", client.addr_string);
+            client.current_animation_frame = 0;
+            client.millis_since_last_frame = 1000;
+            client.last_frame = None;
+            let discover = RdmController::discover(client.universe);
+            if let Err(e) = self.udp_sender.try_send((discover, addr)) {
+                println!("Can not send RDM discovery: {:?}", e);
+            }
+        }
+        client.last_reply_received = now;
+        if let ArtCommand::PollReply(reply) = &command {
+            if let Some(sent) = self.last_poll_sent {
+                client.metrics.reply_received(sent, now);
+            }
+            if let Err(e) = client.update(reply) {
+                println!("Could not update torch {}: {:?}", client.addr_string, e);
+            }
+        }
+    }
+
+    fn error(&mut self, error: Error, _ctx: &mut Context<Self>) -> Running {
+        self.transport
+            .receive_failed(&error, time::precise_time_s());
+        Running::Continue
+    }
+
+    /// The socket stopped, the next tick binds a new one
+    fn finished(&mut self, _ctx: &mut Context<Self>) {
+        self.udp_stream = None;
+        self.transport
+            .set_down(&format_err!("Receiving stopped"), time::precise_time_s());
     }
 }
 
 impl Service {
-    fn init(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+    /// Starts the service outside the registry, with its own config instead of `config.json`
+    pub fn start_with(config: Config) -> Addr<Service> {
+        Service::create(|ctx| {
+            let mut service = Service::new(config);
+            service.init(ctx);
+            service
+        })
+    }
+
+    fn init(&mut self, ctx: &mut Context<Self>) {
         println!("Binding listening address");
-        let socket = NetSocket::bind("0.0.0.0:6454")?;
-
-        #[cfg(unix)]
-        unsafe {
-            use std::os::unix::io::AsRawFd;
-            let optval: libc::c_int = 1;
-            let ret = libc::setsockopt(
-                socket.as_raw_fd(),
-                libc::SOL_SOCKET,
-                libc::SO_REUSEPORT,
-                &optval as *const _ as *const libc::c_void,
-                mem::size_of_val(&optval) as libc::socklen_t,
-            );
-            if ret != 0 {
-                panic!("setsockopt failed");
+        // Without a socket the service still starts, it keeps trying to bind on every tick
+        if let Err(e) = self.connect(ctx) {
+            self.transport.set_down(&e, time::precise_time_s());
+        }
+
+        // Packets are sent from the actor, so a failing send never takes the service down.
+        // Frames do not go through here, they are sent in one batch per tick by `send_frames`.
+        let (sender, receiver) = channel(QUEUE_SIZE);
+        ctx.spawn(
+            wrap_stream::<_, Self>(receiver)
+                .map(|(packet, addr), service, _ctx| service.send_packet(packet, addr))
+                .finish(),
+        );
+        self.udp_sender = sender;
+
+        if let Some(audio) = self.config.audio.clone() {
+            if let Err(e) = audio::start(audio, ctx.address().recipient()) {
+                println!("Could not start audio: {:?}", e);
             }
         }
-        socket.set_broadcast(true)?;
 
+        if let Some(redundancy) = &mut self.redundancy {
+            let address = ctx.address();
+            if let Err(e) = redundancy.start(address.clone().recipient(), address.recipient()) {
+                println!(
+                    "Could not start redundancy, a standby sends no frames: {:?}",
+                    e
+                );
+            }
+        }
+
+        self.tick(ctx);
+        ctx.run_interval(Duration::from_secs(1), Self::tick);
+        ctx.run_interval(Duration::from_millis(33), Self::render);
+    }
+    /// Binds a new socket and receives from it instead of the current one
+    fn connect(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        let socket = artnet::bind()?;
+        let sender = socket.try_clone()?;
         let framed = UdpFramed::new(
             UdpSocket::from_std(socket, &Handle::default())?,
             Codec::default(),
         );
+        if let Some(stream) = self.udp_stream.take() {
+            ctx.cancel_future(stream);
+        }
+        self.udp_stream = Some(Self::add_stream(framed, ctx));
+        self.transport.replace(sender);
+        Ok(())
+    }
 
-        let (sender, receiver) = channel(100);
-        let (sink, stream) = framed.split();
-        let sink_future: Box<dyn Future<Item = (), Error = ()>> = Box::new(
-            sink.sink_map_err(move |e| {
-                panic!("Could not send_all {:?}", e);
-            })
-            .send_all(receiver.map_err(|e| {
-                panic!("Could not receive data from internal receiver: {:?}", e);
-            }))
-            .map(|_| ()),
+    fn send_packet(&mut self, packet: Packet, addr: SocketAddr) {
+        // A standby keeps discovering the torches, but leaves the frames to the primary
+        let frame = matches!(
+            packet,
+            Packet::Dmx(_) | Packet::Command(ArtCommand::Output(_))
         );
-        Self::add_stream(stream, ctx);
-        ctx.spawn(wrap_future(sink_future));
-        self.udp_sender = sender;
+        if frame && !self.is_active() {
+            return;
+        }
+        // Frames and polls are sent again soon anyway, so only the others are retried
+        let retry = !frame && !matches!(packet, Packet::Command(ArtCommand::Poll(_)));
+        let now = time::precise_time_s();
+        // Only the broadcast polls are counted by the metrics, so a poll sent to a single torch
+        // must not move the time the replies are measured from
+        if let Packet::Command(ArtCommand::Poll(_)) = packet {
+            if self.config.broadcasts.contains(&addr) {
+                self.last_poll_sent = Some(now);
+            }
+        }
+        if let Some(buffer) = self.encode_packet(packet, addr, now) {
+            if let Some(client) = self.clients.get_mut(&addr) {
+                client.metrics.sent(buffer.len());
+            }
+            self.transport.send(buffer, addr, retry, now);
+        }
+    }
 
-        self.tick(ctx);
-        ctx.run_interval(Duration::from_secs(1), Self::tick);
-        ctx.run_interval(Duration::from_millis(33), Self::render);
+    /// Encodes a packet, and writes it to the recording if one runs
+    fn encode_packet(&mut self, packet: Packet, addr: SocketAddr, now: f64) -> Option<Vec<u8>> {
+        let buffer = match packet.into_buffer() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                println!("Could not encode packet: {:?}", e);
+                return None;
+            }
+        };
+        if let Some(writer) = &mut self.recorder {
+            if let Err(e) = writer.write(addr, &buffer, now) {
+                println!("Could not record packet, stopping the recording: {:?}", e);
+                if let Some(writer) = self.recorder.take() {
+                    let _ = writer.finish();
+                }
+            }
+        }
+        Some(buffer)
+    }
 
-        Ok(())
+    /// Sends the frames of a tick in one batch. A torch whose frame was dropped gets the next one
+    /// even when it did not change.
+    pub fn send_frames(&mut self, frames: Vec<(SocketAddr, Dmx)>, now: f64) {
+        if frames.is_empty() || !self.is_active() {
+            return;
+        }
+        let start = time::precise_time_s();
+        let batch: Vec<(Vec<u8>, SocketAddr)> = frames
+            .into_iter()
+            .filter_map(|(addr, dmx)| {
+                Some((self.encode_packet(Packet::Dmx(dmx), addr, now)?, addr))
+            })
+            .collect();
+        let dropped: HashSet<SocketAddr> =
+            self.transport.send_batch(&batch, now).into_iter().collect();
+        for (buffer, addr) in &batch {
+            let client = match self.clients.get_mut(addr) {
+                Some(client) => client,
+                None => continue,
+            };
+            if dropped.contains(addr) {
+                client.last_frame = None;
+                client.metrics.frame_dropped();
+            } else {
+                client.metrics.sent(buffer.len());
+            }
+        }
+
+        let time = time::precise_time_s() - start;
+        let stats = &mut self.frame_stats;
+        stats.sent += (batch.len() - dropped.len()) as u64;
+        stats.dropped += dropped.len() as u64;
+        stats.drop_rate = stats.dropped as f64 / (stats.sent + stats.dropped) as f64;
+        stats.batches += 1;
+        stats.last_batch_time = time;
+        stats.average_batch_time += (time - stats.average_batch_time) / stats.batches as f64;
+        stats.max_batch_time = stats.max_batch_time.max(time);
     }
-    fn tick(&mut self, _context: &mut Context<Self>) {
-        for ip in &mut self.config.broadcasts {
+
+    fn tick(&mut self, ctx: &mut Context<Self>) {
+        let now = time::precise_time_s();
+        if self.transport.needs_rebuild(now) {
+            if let Err(e) = self.connect(ctx) {
+                self.transport.set_down(&e, now);
+            }
+        }
+        self.transport.tick(now);
+        if let Some(redundancy) = &mut self.redundancy {
+            if redundancy.check(now) {
+                // Nothing was sent while standing by, so every torch needs a frame right away
+                for client in self.clients.values_mut() {
+                    client.last_frame = None;
+                    client.millis_since_last_frame = 1000;
+                }
+            }
+            redundancy.send_heartbeat(&self.state);
+        }
+        for (addr, client) in &mut self.clients {
+            client.check_pending(now, self.config.client_timeout);
+            // The devices behind it are discovered again when it reappears
+            if !client.is_online(now, self.config.client_timeout) {
+                self.rdm.forget(addr);
+            }
+        }
+        if now - self.last_rdm_discovery >= RDM_DISCOVERY_INTERVAL {
+            self.last_rdm_discovery = now;
+            let discoveries: Vec<_> = self
+                .clients
+                .iter()
+                .map(|(addr, c)| (RdmController::discover(c.universe), *addr))
+                .collect();
+            for discovery in discoveries {
+                if let Err(e) = self.udp_sender.try_send(discovery) {
+                    println!("Can not send RDM discovery: {:?}", e);
+                }
+            }
+        }
+        for (addr, block) in self.firmware.tick(now) {
             if let Err(e) = self
                 .udp_sender
-                .try_send((ArtCommand::Poll(Default::default()), *ip))
+                .try_send((Packet::FirmwareMaster(block), addr))
+            {
+                println!("Can not send firmware: {:?}", e);
+            }
+        }
+        let timeout = self.config.external.timeout;
+        self.external_frames.retain(|universe, (received, _)| {
+            let active = now - *received < timeout;
+            if !active {
+                println!("External stream on universe {} stopped", universe);
+            }
+            active
+        });
+        let mut polled = false;
+        for ip in &mut self.config.broadcasts {
+            match self
+                .udp_sender
+                .try_send((ArtCommand::Poll(Default::default()).into(), *ip))
             {
-                println!("Can not broadcast: {:?}", e);
+                Ok(()) => polled = true,
+                Err(e) => println!("Can not broadcast: {:?}", e),
+            }
+        }
+        if polled {
+            for client in self.clients.values_mut() {
+                client.metrics.poll_sent();
             }
         }
     }
 
     fn render(&mut self, _: &mut Context<Self>) {
+        if !self.is_active() {
+            return;
+        }
+        let now = time::precise_time_s();
+        let frames = self.render_frames(now);
+        self.send_frames(frames, now);
+        self.render_time.observe(time::precise_time_s() - now);
+    }
+
+    /// Renders the frames that are due, called 30 times a second
+    pub fn render_frames(&mut self, now: f64) -> Vec<(SocketAddr, Dmx)> {
+        let mut frames = Vec::new();
         for (addr, client) in &mut self.clients {
-            let (bytes, fps, frame_count): (Vec<u8>, u8, usize) = match &client.current {
-                RenderMode::Color(r, g, b) => (
-                    std::iter::repeat([r, g, b].iter())
-                        .take(7 * 22)
-                        .flatten()
-                        .map(|c| **c)
-                        .collect(),
-                    1,
-                    1,
-                ),
-                RenderMode::Animation(animation_name) => {
-                    match self.animations.animations.get(animation_name.as_str()) {
-                        Some(anim) => {
-                            let frame = anim.frames[client.current_animation_frame];
-                            let bytes = frame
-                                .iter()
-                                .flatten()
-                                .flat_map(|(r, g, b)| vec![r, g, b])
-                                .copied()
-                                .collect();
-                            (bytes, anim.fps, anim.frames.len())
-                        }
-                        None => {
-                            client.current_animation_frame = 0;
-                            continue;
-                        }
+            // Leave the torch alone while it receives new firmware
+            if self.firmware.is_uploading(addr) {
+                continue;
+            }
+            let local = match &client.current {
+                RenderMode::External(_, local) => local,
+                mode => mode,
+            };
+            let rendered = render_local(
+                local,
+                &self.animations,
+                &self.audio,
+                &self.tempo,
+                client.current_animation_frame,
+                now,
+            );
+            let mut rendered = match rendered {
+                Some(rendered) => rendered,
+                None => {
+                    // A one-shot whose animation is gone would never finish
+                    if let RenderMode::OneShot(_, previous) = &client.current {
+                        client.current = (**previous).clone();
                     }
+                    client.current_animation_frame = 0;
+                    continue;
                 }
             };
+            let mut streaming = false;
+            if let RenderMode::External(universe, _) = &client.current {
+                match self.external_frames.get(universe) {
+                    Some((received, data)) if now - received < self.config.external.timeout => {
+                        merge_external(&mut rendered.frame, data, self.config.external.merge);
+                        streaming = true;
+                        rendered.id = None;
+                        rendered.payload = None;
+                    }
+                    _ => {}
+                }
+            }
+            // The local mode advances at its own fps, while an external stream is sent on every tick
             client.millis_since_last_frame += 33;
-            let millis_per_frame = 1000 / usize::from(fps);
-            if client.millis_since_last_frame >= millis_per_frame {
+            let millis_per_frame = 1000 / usize::from(rendered.fps);
+            let local_frame_due = client.millis_since_last_frame >= millis_per_frame;
+            if local_frame_due {
                 client.millis_since_last_frame -= millis_per_frame;
-            } else {
+            } else if !streaming {
                 continue;
             }
-            let message = Output {
-                data: bytes[12..].to_vec(),
-                length: 450,
-                ..Output::default()
-            };
-            assert_eq!(message.length as usize, message.data.len());
-            if let Err(e) = self
-                .udp_sender
-                .try_send((ArtCommand::Output(message), *addr))
+            let send_interval = if streaming { 33 } else { millis_per_frame };
+            let data = self.output.encode(
+                &client.addr_string,
+                rendered.frame,
+                rendered.id,
+                rendered.payload.as_ref(),
+                now,
+            );
+            // Skipping is only done when the next frame still comes before the keepalive is due
+            let next_frame = now + send_interval as f64 / 1000.;
+            if client.last_frame.as_ref() == Some(&data)
+                && next_frame - client.last_frame_sent <= self.config.keepalive
             {
-                println!("Can not send animation: {:?}", e);
-                client.current_animation_frame = 0;
+                self.frame_stats.skipped += 1;
+                self.frame_stats.bytes_saved += (ARTDMX_HEADER_SIZE + data.len()) as u64;
+            } else {
+                let universe = client.universe;
+                frames.push((
+                    *addr,
+                    Dmx {
+                        universe,
+                        data: data.clone(),
+                    },
+                ));
+                client.last_frame = Some(data);
+                client.last_frame_sent = now;
+            }
+            if !local_frame_due {
                 continue;
             }
-            client.current_animation_frame = (client.current_animation_frame + 1) % frame_count;
+            client.current_animation_frame =
+                (client.current_animation_frame + 1) % rendered.frame_count;
+            if client.current_animation_frame == 0 {
+                if let RenderMode::OneShot(_, previous) = &client.current {
+                    client.current = (**previous).clone();
+                }
+            }
+        }
+        self.output.end_tick(now);
+        frames
+    }
+
+    /// Adds a torch that did not send a PollReply, for the benchmark
+    pub fn add_client(&mut self, client: Client) {
+        self.clients.insert(client.socket_address, client);
+    }
+
+    pub fn animation_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.animations.animations.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Sends over `socket` without receiving from it, for the benchmark
+    pub fn set_socket(&mut self, socket: std::net::UdpSocket) {
+        self.transport.replace(socket);
+    }
+
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    fn set_mode(&mut self, ip: &str, mode: RenderMode) -> Result<()> {
+        self.apply_mode(ip, mode)?;
+        self.save_state();
+        Ok(())
+    }
+
+    /// Sets the mode of a torch without saving the state, so several changes can be saved at once
+    fn apply_mode(&mut self, ip: &str, mode: RenderMode) -> Result<()> {
+        if self.output.blackout {
+            bail!("Blackout is active, release it first");
+        }
+        for client in self.clients.values_mut() {
+            if client.addr_string == ip {
+                client.current = mode;
+                client.current_animation_frame = 0;
+                client.millis_since_last_frame = 1000;
+                self.state.set_mode(client);
+                self.state.scene_pending.retain(|pending| pending != ip);
+                return Ok(());
+            }
+        }
+        bail!("Torch with ip {} not found", ip)
+    }
+
+    fn recall_scene(&mut self, name: &str) -> Result<()> {
+        self.apply_scene(name)?;
+        self.save_state();
+        Ok(())
+    }
+
+    fn apply_scene(&mut self, name: &str) -> Result<()> {
+        if self.output.blackout {
+            bail!("Blackout is active, release it first");
+        }
+        let scene = match self.config.scenes.iter().find(|s| s.name == name) {
+            Some(scene) => scene.clone(),
+            None => bail!("Scene {:?} not found", name),
+        };
+        println!("Recalling scene {:?}", scene.name);
+        let mut pending = Vec::new();
+        for (ip, mode) in scene.torches {
+            if !self.clients.values().any(|client| client.addr_string == ip) {
+                pending.push(ip);
+            } else if let Err(e) = self.apply_mode(&ip, mode) {
+                println!("Could not apply scene {:?} to {}: {:?}", scene.name, ip, e);
+            }
         }
+        self.state.scene = Some(scene.name);
+        self.state.scene_pending = pending;
+        Ok(())
+    }
+
+    /// The mode in the last recalled scene of a torch that was offline at the time, only once
+    fn take_scene_mode(&mut self, ip: &str) -> Option<RenderMode> {
+        let index = self.state.scene_pending.iter().position(|p| p == ip)?;
+        self.state.scene_pending.remove(index);
+        let name = self.state.scene.as_ref()?;
+        let scene = self
+            .config
+            .scenes
+            .iter()
+            .find(|scene| &scene.name == name)?;
+        scene.torches.get(ip).cloned()
+    }
+
+    fn set_blackout(&mut self, latched: bool) {
+        self.apply_blackout(latched);
+        self.save_state();
     }
+
+    fn apply_blackout(&mut self, latched: bool) {
+        self.output.blackout = latched;
+        self.state.blackout = latched;
+        if latched {
+            println!("Blackout latched");
+            let data = output::encode(&AnimationFrame::default());
+            let frames = self
+                .clients
+                .iter()
+                .map(|(addr, client)| {
+                    let universe = client.universe;
+                    (
+                        *addr,
+                        Dmx {
+                            universe,
+                            data: data.clone(),
+                        },
+                    )
+                })
+                .collect();
+            self.send_frames(frames, time::precise_time_s());
+        } else {
+            println!("Blackout released");
+        }
+        // Make sure every torch gets a new frame on the next render, so the previous modes return immediately
+        for client in self.clients.values_mut() {
+            client.millis_since_last_frame = 1000;
+        }
+    }
+
+    fn send_all(&mut self, packets: Vec<Packet>, addr: SocketAddr) {
+        for packet in packets {
+            if let Err(e) = self.udp_sender.try_send((packet, addr)) {
+                println!("Can not send to {}: {:?}", addr, e);
+            }
+        }
+    }
+
+    /// Sends an ArtAddress or ArtIpProg to a torch, its next PollReply should confirm `pending`
+    fn send_node_config(&mut self, ip: &str, packet: Packet, pending: PendingChange) -> Result<()> {
+        let (addr, client) = match self.clients.iter_mut().find(|(_, c)| c.addr_string == ip) {
+            Some(client) => client,
+            None => bail!("Torch with ip {} not found", ip),
+        };
+        self.udp_sender.try_send((packet, *addr))?;
+        // Nodes reply to ArtAddress with a PollReply, but not to ArtIpProg
+        self.udp_sender
+            .try_send((ArtCommand::Poll(Default::default()).into(), *addr))?;
+        client.set_pending(pending);
+        Ok(())
+    }
+
+    fn handle_trigger(&mut self, trigger: &Trigger) {
+        let actions: Vec<Action> = self
+            .config
+            .triggers
+            .iter()
+            .filter(|t| {
+                t.oem == trigger.oem && t.key == trigger.key && t.sub_key == trigger.sub_key
+            })
+            .map(|t| t.action.clone())
+            .collect();
+        for action in actions {
+            if let Err(e) = self.apply_action(action) {
+                println!("Could not apply trigger: {:?}", e);
+            }
+        }
+    }
+
+    fn apply_action(&mut self, action: Action) -> Result<()> {
+        match action {
+            Action::Scene(name) => self.recall_scene(&name),
+            Action::OneShot { animation, torches } => self.play_one_shot(animation, &torches),
+        }
+    }
+
+    /// The torches with the given IPs or in the given groups, all torches if empty
+    fn targets(&self, torches: &[String]) -> Result<Vec<SocketAddr>> {
+        let ips: Vec<&String> = torches
+            .iter()
+            .flat_map(|t| match self.config.groups.get(t) {
+                Some(group) => group.iter().collect(),
+                None => vec![t],
+            })
+            .collect();
+        // An empty group should not mean every torch
+        if ips.is_empty() && !torches.is_empty() {
+            bail!("No torches in {}", torches.join(", "));
+        }
+        Ok(self
+            .clients
+            .iter()
+            .filter(|(_, c)| ips.is_empty() || ips.contains(&&c.addr_string))
+            .map(|(addr, _)| *addr)
+            .collect())
+    }
+
+    /// Plays an animation once on the given IPs and groups, or on all torches if empty
+    fn play_one_shot(&mut self, animation: String, torches: &[String]) -> Result<()> {
+        if self.output.blackout {
+            bail!("Blackout is active, release it first");
+        }
+        if !self.animations.animations.contains_key(&animation) {
+            bail!("Animation {:?} not found", animation);
+        }
+        for addr in self.targets(torches)? {
+            let client = self.clients.get_mut(&addr).expect("Unreachable");
+            let previous = match &client.current {
+                RenderMode::OneShot(_, previous) => previous.clone(),
+                mode => Box::new(mode.clone()),
+            };
+            client.current = RenderMode::OneShot(animation.clone(), previous);
+            client.current_animation_frame = 0;
+            client.millis_since_last_frame = 1000;
+        }
+        Ok(())
+    }
+
+    fn handle_dmx_input(&mut self, data: &[u8]) {
+        let actions = match &mut self.input {
+            Some(input) => input.update(data),
+            None => return,
+        };
+        // A fader sends a packet per step, so the changes of a packet are saved at once
+        let mut changed = false;
+        for action in actions {
+            let result = match action {
+                InputAction::MasterBrightness(brightness) => {
+                    self.output.brightness.master = brightness;
+                    self.state.master_brightness = Some(brightness);
+                    Ok(())
+                }
+                InputAction::Scene(number) => match self.config.scenes.get(number - 1) {
+                    Some(scene) => {
+                        let name = scene.name.clone();
+                        self.apply_scene(&name)
+                    }
+                    None => Err(format_err!("Scene {} not found", number)),
+                },
+                InputAction::Color(r, g, b) => {
+                    let ips: Vec<String> = self
+                        .clients
+                        .values()
+                        .map(|c| c.addr_string.clone())
+                        .collect();
+                    ips.iter()
+                        .try_for_each(|ip| self.apply_mode(ip, RenderMode::Color(r, g, b)))
+                }
+                InputAction::Blackout(latched) => {
+                    self.apply_blackout(latched);
+                    Ok(())
+                }
+                InputAction::TapTempo => {
+                    self.tempo.tap(time::precise_time_s());
+                    self.state.bpm = Some(self.tempo.bpm());
+                    Ok(())
+                }
+            };
+            match result {
+                Ok(()) => changed = true,
+                Err(e) => println!("Could not apply DMX input: {:?}", e),
+            }
+        }
+        if changed {
+            self.save_state();
+        }
+    }
+
+    /// The metrics of every torch, ordered by IP
+    fn node_metrics(&self) -> Vec<NodeMetricsEntry> {
+        let now = time::precise_time_s();
+        let mut clients: Vec<&Client> = self.clients.values().collect();
+        clients.sort_by_key(|client| client.addr);
+        clients
+            .into_iter()
+            .map(|client| NodeMetricsEntry {
+                ip: client.addr_string.clone(),
+                short_name: client.short_name.clone(),
+                online: client.is_online(now, self.config.client_timeout),
+                metrics: client.metrics.status(),
+            })
+            .collect()
+    }
+
+    /// Writes the changed state to disk right away, it is replicated on the next tick
+    fn save_state(&mut self) {
+        match &self.redundancy {
+            Some(redundancy) => redundancy.touch(&mut self.state),
+            None => self.state.touch(Version::default(), false),
+        }
+        self.write_state();
+    }
+
+    fn write_state(&self) {
+        if let Err(e) = self.state.save(&self.config.state_file) {
+            println!("Could not save state: {:?}", e);
+        }
+    }
+
+    /// Whether this instance sends frames to the torches, a standby sends none while the primary is alive
+    fn is_active(&self) -> bool {
+        self.redundancy.as_ref().is_none_or(Redundancy::is_active)
+    }
+
+    /// Replaces the state by the newer one of the other instance of a redundant pair. Running
+    /// one-shots and the timeline position are not part of the state, so they stay as they are.
+    fn apply_state(&mut self, state: State) {
+        println!("Applying the state of the other instance");
+        let now = time::precise_time_s();
+        if let Some(brightness) = state.master_brightness {
+            self.output.brightness.master = brightness;
+        }
+        // Groups the other instance has no brightness for are back at full brightness
+        self.output.dimmers.clear();
+        for (group, brightness) in &state.group_brightness {
+            for ip in self.config.groups.get(group).into_iter().flatten() {
+                self.output.dimmers.insert(ip.clone(), *brightness);
+            }
+        }
+        if let Some(bpm) = state.bpm.filter(|bpm| Some(*bpm) != self.state.bpm) {
+            self.tempo.set_bpm(bpm, now);
+        }
+        if state.blackout != self.output.blackout {
+            self.apply_blackout(state.blackout);
+        }
+        for (name, profile) in &state.calibration_profiles {
+            self.output.set_calibration_profile(name.clone(), profile.clone());
+        }
+        for (ip, profile) in &state.calibration_torches {
+            self.output.set_torch_calibration(ip.clone(), profile.clone());
+        }
+        for client in self.clients.values_mut() {
+            if let Some(mode) = state.mode_for(client) {
+                if mode != client.current {
+                    client.current = mode;
+                    client.current_animation_frame = 0;
+                    client.millis_since_last_frame = 1000;
+                }
+            }
+        }
+        // Keeps the version of the change, so the other instance does not get it back
+        self.state = state;
+        self.write_state();
+    }
+}
+
+/// A frame rendered for a torch, before the output stage
+struct Rendered {
+    frame: AnimationFrame,
+    fps: u8,
+    /// The amount of frames after which the mode starts over
+    frame_count: usize,
+    /// Set when other torches could show the same frame, see `OutputStage::encode`
+    id: Option<FrameId>,
+    /// The frame encoded when it was loaded
+    payload: Option<Bytes>,
+}
+
+impl Rendered {
+    fn generated(frame: AnimationFrame) -> Rendered {
+        Rendered {
+            frame,
+            fps: 30,
+            frame_count: 1,
+            id: None,
+            payload: None,
+        }
+    }
+
+    fn animation(animation: &Animation, index: usize, fps: u8, frame_count: usize) -> Rendered {
+        Rendered {
+            frame: animation.frames[index],
+            fps,
+            frame_count,
+            id: Some(FrameId::Animation(animation.id, index)),
+            payload: Some(animation.payloads[index].clone()),
+        }
+    }
+}
+
+/// Renders a frame of every mode except `External`
+fn render_local(
+    mode: &RenderMode,
+    animations: &AnimationHandler,
+    audio: &AudioState,
+    tempo: &TempoClock,
+    animation_frame: usize,
+    now: f64,
+) -> Option<Rendered> {
+    Some(match mode {
+        RenderMode::Color(r, g, b) => Rendered {
+            frame: [[(*r, *g, *b); 7]; 22],
+            fps: 1,
+            frame_count: 1,
+            id: Some(FrameId::Color(*r, *g, *b)),
+            payload: None,
+        },
+        RenderMode::Vu(r, g, b) => Rendered::generated(audio::vu(audio.level, (*r, *g, *b))),
+        RenderMode::BeatFlash(r, g, b) => {
+            Rendered::generated(audio::beat_flash(audio.beat_intensity(now), (*r, *g, *b)))
+        }
+        RenderMode::Spectrum => Rendered::generated(audio::spectrum(&audio.bands)),
+        RenderMode::BeatAnimation(animation_name) => {
+            let anim = animations.animations.get(animation_name.as_str())?;
+            Rendered::animation(anim, audio.beats % anim.frames.len(), 30, 1)
+        }
+        RenderMode::TempoAnimation(animation_name, frames_per_beat) => {
+            let anim = animations.animations.get(animation_name.as_str())?;
+            let frame = tempo.beats(now) * f64::from(*frames_per_beat);
+            Rendered::animation(anim, frame as usize % anim.frames.len(), 30, 1)
+        }
+        RenderMode::Animation(animation_name) | RenderMode::OneShot(animation_name, _) => {
+            let anim = animations.animations.get(animation_name.as_str())?;
+            Rendered::animation(anim, animation_frame, anim.fps, anim.frames.len())
+        }
+        RenderMode::External(_, local) => {
+            render_local(local, animations, audio, tempo, animation_frame, now)?
+        }
+    })
+}
+
+/// Merges DMX data, laid out like the frames sent to the torches, into a locally rendered frame
+fn merge_external(frame: &mut AnimationFrame, data: &[u8], mode: MergeMode) {
+    // The first 4 pixels are not sent, see `output::encode`
+    let pixels = frame.iter_mut().flatten().skip(4);
+    for ((r, g, b), channels) in pixels.zip(data.chunks_exact(3)) {
+        let external = (channels[0], channels[1], channels[2]);
+        match mode {
+            MergeMode::Htp => {
+                *r = (*r).max(external.0);
+                *g = (*g).max(external.1);
+                *b = (*b).max(external.2);
+            }
+            MergeMode::Ltp => {
+                *r = external.0;
+                *g = external.1;
+                *b = external.2;
+            }
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Result<(u8, u8, u8)> {
+    if color.len() != 6 {
+        bail!("Color invalid, should be 6-character hexadecimal");
+    }
+    let r: u8 = u8::from_str_radix(&color[0..2], 16)
+        .context("Color invalid, should be 6-character hexadecimal")?;
+    let g: u8 = u8::from_str_radix(&color[2..4], 16)
+        .context("Color invalid, should be 6-character hexadecimal")?;
+    let b: u8 = u8::from_str_radix(&color[4..6], 16)
+        .context("Color invalid, should be 6-character hexadecimal")?;
+    Ok((r, g, b))
 }
 
 impl Handler<AddAnimation> for Service {
@@ -249,12 +1163,55 @@ impl Handler<RequestNodeList> for Service {
         _context: &mut Self::Context,
     ) -> Self::Result {
         let result = ResponseNodeList {
-            nodes: self.clients.values().map(Client::get_node).collect(),
+            nodes: self
+                .clients
+                .iter()
+                .map(|(addr, client)| {
+                    let mut node = client.get_node();
+                    node.flash_violations =
+                        self.output.flash_limiter.violations(&client.addr_string);
+                    node.rdm = self.rdm.devices(addr);
+                    node
+                })
+                .collect(),
         };
         Ok(result)
     }
 }
 
+impl Handler<RequestNodeMetrics> for Service {
+    type Result = <RequestNodeMetrics as Message>::Result;
+
+    fn handle(
+        &mut self,
+        _metrics: RequestNodeMetrics,
+        _context: &mut Self::Context,
+    ) -> Self::Result {
+        Ok(ResponseNodeMetrics {
+            nodes: self.node_metrics(),
+        })
+    }
+}
+
+impl Handler<RequestMetrics> for Service {
+    type Result = <RequestMetrics as Message>::Result;
+
+    fn handle(&mut self, _metrics: RequestMetrics, _context: &mut Self::Context) -> Self::Result {
+        Ok(ResponseMetrics {
+            nodes: self.node_metrics(),
+            frames: self.frame_stats.clone(),
+            udp: self.transport.status(time::precise_time_s()),
+            decode: self.decode_stats.status(),
+            animations: self.animations.animations.len(),
+            render_time: self.render_time.clone(),
+            redundancy: self
+                .redundancy
+                .as_ref()
+                .map(|redundancy| redundancy.status(time::precise_time_s())),
+        })
+    }
+}
+
 impl Handler<SetNodeAnimation> for Service {
     type Result = <SetNodeAnimation as Message>::Result;
 
@@ -271,15 +1228,10 @@ impl Handler<SetNodeAnimation> for Service {
         {
             bail!("Animation not found");
         }
-        for client in self.clients.values_mut() {
-            if client.addr_string == animation.ip {
-                client.current = RenderMode::Animation(animation.animation_name);
-                client.current_animation_frame = 0;
-                client.millis_since_last_frame = 1000;
-                return Ok(());
-            }
-        }
-        bail!("Torch with ip {} not found", animation.ip)
+        self.set_mode(
+            &animation.ip,
+            RenderMode::Animation(animation.animation_name),
+        )
     }
 }
 
@@ -287,23 +1239,503 @@ impl Handler<SetNodeColor> for Service {
     type Result = <SetNodeColor as Message>::Result;
 
     fn handle(&mut self, color: SetNodeColor, _context: &mut Self::Context) -> Self::Result {
-        if color.color_name.len() != 6 {
-            bail!("Color invalid, should be 6-character hexadecimal");
-        }
-        let r: u8 = u8::from_str_radix(&color.color_name[0..2], 16)
-            .context("Color invalid, should be 6-character hexadecimal")?;
-        let g: u8 = u8::from_str_radix(&color.color_name[2..4], 16)
-            .context("Color invalid, should be 6-character hexadecimal")?;
-        let b: u8 = u8::from_str_radix(&color.color_name[4..6], 16)
-            .context("Color invalid, should be 6-character hexadecimal")?;
-        for client in self.clients.values_mut() {
-            if client.addr_string == color.ip {
-                client.current = RenderMode::Color(r, g, b);
-                client.current_animation_frame = 0;
-                client.millis_since_last_frame = 1000;
-                return Ok(());
+        let (r, g, b) = parse_color(&color.color_name)?;
+        self.set_mode(&color.ip, RenderMode::Color(r, g, b))
+    }
+}
+
+impl Handler<SetNodeAudio> for Service {
+    type Result = <SetNodeAudio as Message>::Result;
+
+    fn handle(&mut self, audio: SetNodeAudio, _context: &mut Self::Context) -> Self::Result {
+        if self.config.audio.is_none() {
+            bail!("No audio source configured");
+        }
+        let value = audio.value.as_deref();
+        let mode = match (audio.mode.as_str(), value) {
+            ("vu", Some(color)) => {
+                let (r, g, b) = parse_color(color)?;
+                RenderMode::Vu(r, g, b)
+            }
+            ("beat_flash", Some(color)) => {
+                let (r, g, b) = parse_color(color)?;
+                RenderMode::BeatFlash(r, g, b)
+            }
+            ("spectrum", None) => RenderMode::Spectrum,
+            ("beat_animation", Some(name)) => {
+                if !self.animations.animations.contains_key(name) {
+                    bail!("Animation not found");
+                }
+                RenderMode::BeatAnimation(name.to_owned())
+            }
+            ("vu", None) | ("beat_flash", None) => bail!("Missing color"),
+            ("beat_animation", None) => bail!("Missing animation name"),
+            ("spectrum", Some(_)) => bail!("Spectrum does not take a value"),
+            (mode, _) => bail!(
+                "Unknown audio mode {:?}, should be vu, beat_flash, spectrum or beat_animation",
+                mode
+            ),
+        };
+        self.set_mode(&audio.ip, mode)
+    }
+}
+
+impl Handler<AudioUpdate> for Service {
+    type Result = <AudioUpdate as Message>::Result;
+
+    fn handle(&mut self, update: AudioUpdate, _context: &mut Self::Context) -> Self::Result {
+        self.audio.update(&update.0, time::precise_time_s());
+    }
+}
+
+impl Handler<SetMasterBrightness> for Service {
+    type Result = <SetMasterBrightness as Message>::Result;
+
+    fn handle(
+        &mut self,
+        brightness: SetMasterBrightness,
+        _context: &mut Self::Context,
+    ) -> Self::Result {
+        if brightness.brightness > 100 {
+            bail!("Brightness invalid, should be between 0 and 100");
+        }
+        self.output.brightness.master = brightness.brightness;
+        self.state.master_brightness = Some(brightness.brightness);
+        self.save_state();
+        Ok(())
+    }
+}
+
+impl Handler<RequestCalibration> for Service {
+    type Result = <RequestCalibration as Message>::Result;
+
+    fn handle(
+        &mut self,
+        _calibration: RequestCalibration,
+        _context: &mut Self::Context,
+    ) -> Self::Result {
+        Ok(self.output.calibration().clone())
+    }
+}
+
+impl Handler<SetCalibration> for Service {
+    type Result = <SetCalibration as Message>::Result;
+
+    fn handle(
+        &mut self,
+        calibration: SetCalibration,
+        _context: &mut Self::Context,
+    ) -> Self::Result {
+        let mut profile = self.output.calibration_profile(&calibration.profile);
+        let values = if calibration.values == "none" {
+            Vec::new()
+        } else {
+            calibration
+                .values
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<std::result::Result<Vec<f32>, _>>()
+                .context("Values invalid, should be comma-separated numbers or none")?
+        };
+        match (calibration.setting.as_str(), values.as_slice()) {
+            ("gamma", &[r, g, b]) => profile.gamma = [r, g, b],
+            ("gains", &[r, g, b]) => profile.gains = [r, g, b],
+            ("matrix", &[]) => profile.matrix = None,
+            ("matrix", &[rr, rg, rb, gr, gg, gb, br, bg, bb]) => {
+                profile.matrix = Some([[rr, rg, rb], [gr, gg, gb], [br, bg, bb]])
+            }
+            ("gamma", _) | ("gains", _) => bail!("Expected 3 values, got {}", values.len()),
+            ("matrix", _) => bail!("Expected 9 values, got {}", values.len()),
+            (setting, _) => bail!(
+                "Unknown calibration setting {:?}, should be gamma, gains or matrix",
+                setting
+            ),
+        }
+        profile.validate()?;
+        self.output
+            .set_calibration_profile(calibration.profile.clone(), profile.clone());
+        self.state
+            .calibration_profiles
+            .insert(calibration.profile, profile);
+        self.save_state();
+        Ok(())
+    }
+}
+
+impl Handler<SetNodeCalibration> for Service {
+    type Result = <SetNodeCalibration as Message>::Result;
+
+    fn handle(
+        &mut self,
+        calibration: SetNodeCalibration,
+        _context: &mut Self::Context,
+    ) -> Self::Result {
+        if !self
+            .output
+            .calibration()
+            .profiles
+            .contains_key(&calibration.profile)
+        {
+            bail!("Calibration profile not found");
+        }
+        self.output
+            .set_torch_calibration(calibration.ip.clone(), calibration.profile.clone());
+        self.state
+            .calibration_torches
+            .insert(calibration.ip, calibration.profile);
+        self.save_state();
+        Ok(())
+    }
+}
+
+impl Handler<SetBlackout> for Service {
+    type Result = <SetBlackout as Message>::Result;
+
+    fn handle(&mut self, blackout: SetBlackout, _context: &mut Self::Context) -> Self::Result {
+        self.set_blackout(blackout.latched);
+        Ok(())
+    }
+}
+
+impl Handler<SetNodeAddress> for Service {
+    type Result = <SetNodeAddress as Message>::Result;
+
+    fn handle(&mut self, address: SetNodeAddress, _context: &mut Self::Context) -> Self::Result {
+        let mut packet = Address::default();
+        let mut pending = PendingChange {
+            sent: time::precise_time_s(),
+            ..PendingChange::default()
+        };
+        match address.setting.as_str() {
+            "short_name" => {
+                if address.value.is_empty() || address.value.len() > 17 {
+                    bail!("Short name should be 1 to 17 bytes");
+                }
+                packet.short_name = Some(address.value.clone());
+                pending.short_name = Some(address.value);
+            }
+            "long_name" => {
+                if address.value.is_empty() || address.value.len() > 63 {
+                    bail!("Long name should be 1 to 63 bytes");
+                }
+                packet.long_name = Some(address.value.clone());
+                pending.long_name = Some(address.value);
+            }
+            "universe" => {
+                let universe = address.value.parse::<u16>().context("Universe invalid")?;
+                if universe > 0x7FFF {
+                    bail!("Universe should be at most 32767");
+                }
+                packet.universe = Some(universe);
+                pending.universe = Some(universe);
             }
+            // Commands do not show up in a PollReply, so there is nothing to confirm
+            "command" => packet.command = AddressCommand::parse(&address.value)?,
+            setting => bail!("Unknown setting {:?}", setting),
         }
-        bail!("Torch with ip {} not found", color.ip)
+        self.send_node_config(&address.ip, Packet::Address(packet), pending)
+    }
+}
+
+impl Handler<SetNodeIp> for Service {
+    type Result = <SetNodeIp as Message>::Result;
+
+    fn handle(&mut self, ip: SetNodeIp, _context: &mut Self::Context) -> Self::Result {
+        let mut pending = PendingChange {
+            sent: time::precise_time_s(),
+            ..PendingChange::default()
+        };
+        let packet = match ip.address {
+            Some((address, mask)) => {
+                let address = address.parse::<Ipv4Addr>().context("IP address invalid")?;
+                let mask = mask.parse::<Ipv4Addr>().context("Subnet mask invalid")?;
+                pending.address = Some(address.to_string());
+                pending.dhcp = Some(false);
+                IpProg::Static { address, mask }
+            }
+            None => {
+                pending.dhcp = Some(true);
+                IpProg::Dhcp
+            }
+        };
+        self.send_node_config(&ip.ip, Packet::IpProg(packet), pending)
+    }
+}
+
+impl Handler<AddFirmware> for Service {
+    type Result = <AddFirmware as Message>::Result;
+
+    fn handle(&mut self, firmware: AddFirmware, _context: &mut Self::Context) -> Self::Result {
+        println!(
+            "Adding firmware {:?} ({} bytes)",
+            firmware.name,
+            firmware.bytes.len()
+        );
+        self.firmware.add(&firmware.name, firmware.bytes)
+    }
+}
+
+impl Handler<RequestFirmware> for Service {
+    type Result = <RequestFirmware as Message>::Result;
+
+    fn handle(&mut self, _firmware: RequestFirmware, _context: &mut Self::Context) -> Self::Result {
+        Ok(self.firmware.status())
+    }
+}
+
+impl Handler<PushFirmware> for Service {
+    type Result = <PushFirmware as Message>::Result;
+
+    fn handle(&mut self, push: PushFirmware, _context: &mut Self::Context) -> Self::Result {
+        let torches: Vec<String> = match push.torches.as_str() {
+            "all" => Vec::new(),
+            torches => torches.split(',').map(String::from).collect(),
+        };
+        let targets: Vec<(SocketAddr, String)> = self
+            .targets(&torches)?
+            .into_iter()
+            .map(|addr| (addr, self.clients[&addr].addr_string.clone()))
+            .collect();
+        if targets.is_empty() {
+            bail!("No torches found");
+        }
+        // Check every torch first, so no update starts when one of them can not be updated
+        for (addr, ip) in &targets {
+            self.firmware.check(addr, ip, &push.firmware)?;
+        }
+        let now = time::precise_time_s();
+        let mut failed = Vec::new();
+        for (addr, ip) in targets {
+            let block = self.firmware.start(addr, &ip, &push.firmware, now)?;
+            if let Err(e) = self
+                .udp_sender
+                .try_send((Packet::FirmwareMaster(block), addr))
+            {
+                println!("Can not send firmware to {}: {:?}", ip, e);
+                self.firmware.cancel(&addr);
+                failed.push(ip);
+                continue;
+            }
+            println!("Updating torch {} to firmware {:?}", ip, push.firmware);
+        }
+        if !failed.is_empty() {
+            bail!("Could not start the update of {}", failed.join(", "));
+        }
+        Ok(())
+    }
+}
+
+impl Handler<RequestTimeline> for Service {
+    type Result = <RequestTimeline as Message>::Result;
+
+    fn handle(&mut self, _timeline: RequestTimeline, _context: &mut Self::Context) -> Self::Result {
+        Ok(self.timeline.status(time::precise_time_s()))
+    }
+}
+
+impl Handler<SetTimecodeLock> for Service {
+    type Result = <SetTimecodeLock as Message>::Result;
+
+    fn handle(&mut self, lock: SetTimecodeLock, _context: &mut Self::Context) -> Self::Result {
+        self.timeline.set_locked(lock.locked);
+        Ok(())
+    }
+}
+
+impl Handler<RequestHealth> for Service {
+    type Result = <RequestHealth as Message>::Result;
+
+    fn handle(&mut self, _health: RequestHealth, _context: &mut Self::Context) -> Self::Result {
+        Ok(ResponseHealth {
+            udp: self.transport.status(time::precise_time_s()),
+            decode: self.decode_stats.status(),
+            frames: self.frame_stats.clone(),
+            redundancy: self
+                .redundancy
+                .as_ref()
+                .map(|redundancy| redundancy.status(time::precise_time_s())),
+        })
+    }
+}
+
+impl Handler<PeerHeartbeat> for Service {
+    type Result = <PeerHeartbeat as Message>::Result;
+
+    fn handle(&mut self, heartbeat: PeerHeartbeat, _context: &mut Self::Context) -> Self::Result {
+        let now = time::precise_time_s();
+        let redundancy = match &mut self.redundancy {
+            Some(redundancy) => redundancy,
+            None => return,
+        };
+        redundancy.heartbeat(heartbeat.0, now);
+    }
+}
+
+impl Handler<PeerState> for Service {
+    type Result = <PeerState as Message>::Result;
+
+    fn handle(&mut self, state: PeerState, _context: &mut Self::Context) -> Self::Result {
+        let redundancy = match &mut self.redundancy {
+            Some(redundancy) => redundancy,
+            None => return,
+        };
+        if let Some(state) = redundancy.receive_state(state.0, &self.state) {
+            self.apply_state(state);
+        }
+    }
+}
+
+impl Handler<StartRecording> for Service {
+    type Result = <StartRecording as Message>::Result;
+
+    fn handle(&mut self, _start: StartRecording, _context: &mut Self::Context) -> Self::Result {
+        if self.recorder.is_some() {
+            bail!("Already recording");
+        }
+        let writer = CaptureWriter::create(time::precise_time_s())?;
+        let status = writer.status(time::precise_time_s());
+        println!("Recording to {:?}", status.file);
+        self.recorder = Some(writer);
+        Ok(status)
+    }
+}
+
+impl Handler<StopRecording> for Service {
+    type Result = <StopRecording as Message>::Result;
+
+    fn handle(&mut self, _stop: StopRecording, _context: &mut Self::Context) -> Self::Result {
+        let writer = match self.recorder.take() {
+            Some(writer) => writer,
+            None => bail!("Not recording"),
+        };
+        let status = writer.status(time::precise_time_s());
+        let path = writer.finish()?;
+        println!("Recorded {} packets to {}", status.packets, path);
+        Ok(RecordingStatus {
+            recording: false,
+            ..status
+        })
+    }
+}
+
+impl Handler<RequestRecording> for Service {
+    type Result = <RequestRecording as Message>::Result;
+
+    fn handle(&mut self, _request: RequestRecording, _context: &mut Self::Context) -> Self::Result {
+        Ok(match &self.recorder {
+            Some(writer) => writer.status(time::precise_time_s()),
+            None => RecordingStatus::idle(),
+        })
+    }
+}
+
+impl Handler<RequestTempo> for Service {
+    type Result = <RequestTempo as Message>::Result;
+
+    fn handle(&mut self, _tempo: RequestTempo, _context: &mut Self::Context) -> Self::Result {
+        Ok(self.tempo.tempo(time::precise_time_s()))
+    }
+}
+
+impl Handler<TapTempo> for Service {
+    type Result = <TapTempo as Message>::Result;
+
+    fn handle(&mut self, _tap: TapTempo, _context: &mut Self::Context) -> Self::Result {
+        let now = time::precise_time_s();
+        self.tempo.tap(now);
+        self.state.bpm = Some(self.tempo.bpm());
+        self.save_state();
+        Ok(self.tempo.tempo(now))
+    }
+}
+
+impl Handler<SetBpm> for Service {
+    type Result = <SetBpm as Message>::Result;
+
+    fn handle(&mut self, bpm: SetBpm, _context: &mut Self::Context) -> Self::Result {
+        if !(bpm.bpm >= 1. && bpm.bpm <= 1000.) {
+            bail!("BPM invalid, should be between 1 and 1000");
+        }
+        let now = time::precise_time_s();
+        self.tempo.set_bpm(bpm.bpm, now);
+        self.state.bpm = Some(bpm.bpm);
+        self.save_state();
+        Ok(self.tempo.tempo(now))
+    }
+}
+
+impl Handler<SetNodeTempoAnimation> for Service {
+    type Result = <SetNodeTempoAnimation as Message>::Result;
+
+    fn handle(
+        &mut self,
+        animation: SetNodeTempoAnimation,
+        _context: &mut Self::Context,
+    ) -> Self::Result {
+        if !self
+            .animations
+            .animations
+            .contains_key(&animation.animation_name)
+        {
+            bail!("Animation not found");
+        }
+        if animation.frames_per_beat == 0 {
+            bail!("Frames per beat should be at least 1");
+        }
+        self.set_mode(
+            &animation.ip,
+            RenderMode::TempoAnimation(animation.animation_name, animation.frames_per_beat),
+        )
+    }
+}
+
+impl Handler<SetNodeExternal> for Service {
+    type Result = <SetNodeExternal as Message>::Result;
+
+    fn handle(&mut self, external: SetNodeExternal, _context: &mut Self::Context) -> Self::Result {
+        let local = match self.clients.values().find(|c| c.addr_string == external.ip) {
+            Some(client) => match &client.current {
+                RenderMode::External(_, local) => local.clone(),
+                mode => Box::new(mode.clone()),
+            },
+            None => bail!("Torch with ip {} not found", external.ip),
+        };
+        self.set_mode(&external.ip, RenderMode::External(external.universe, local))
+    }
+}
+
+impl Handler<SetGroupBrightness> for Service {
+    type Result = <SetGroupBrightness as Message>::Result;
+
+    fn handle(
+        &mut self,
+        brightness: SetGroupBrightness,
+        _context: &mut Self::Context,
+    ) -> Self::Result {
+        if brightness.brightness > 100 {
+            bail!("Brightness invalid, should be between 0 and 100");
+        }
+        let ips = match self.config.groups.get(&brightness.group) {
+            Some(ips) => ips,
+            None => bail!("Group {:?} not found", brightness.group),
+        };
+        for ip in ips {
+            self.output
+                .dimmers
+                .insert(ip.clone(), brightness.brightness);
+        }
+        self.state
+            .group_brightness
+            .insert(brightness.group, brightness.brightness);
+        self.save_state();
+        Ok(())
+    }
+}
+
+impl Handler<RecallScene> for Service {
+    type Result = <RecallScene as Message>::Result;
+
+    fn handle(&mut self, scene: RecallScene, _context: &mut Self::Context) -> Self::Result {
+        self.recall_scene(&scene.name)
     }
 }
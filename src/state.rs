@@ -0,0 +1,89 @@
+use crate::artnet::{Client, RenderMode};
+use crate::config::CalibrationProfile;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+
+/// Everything that was changed through the API and should survive a restart
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct State {
+    /// The assigned mode of each torch, keyed by the MAC address of the torch, or the IP if it has no MAC address
+    pub torches: HashMap<String, RenderMode>,
+    pub master_brightness: Option<u8>,
+    pub blackout: bool,
+    pub bpm: Option<f64>,
+    /// Brightness in percent of each group
+    pub group_brightness: HashMap<String, u8>,
+    /// The scene that was recalled last
+    pub scene: Option<String>,
+    /// The torches of `scene` that were offline when it was recalled, by IP. They get their mode
+    /// from the scene when they are discovered.
+    pub scene_pending: Vec<String>,
+    /// Calibration profiles changed through the API, they replace the ones in the config
+    pub calibration_profiles: HashMap<String, CalibrationProfile>,
+    /// The calibration profile of each torch set through the API, keyed by the IP of the torch
+    pub calibration_torches: HashMap<String, String>,
+    /// Of the last change, the higher version wins when a redundant pair replicates
+    pub version: Version,
+}
+
+/// Orders the changes of the two instances of a redundant pair, without relying on their clocks
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    /// Bumped past the counters of both instances on every change
+    pub counter: u64,
+    /// Whether the primary made the change, it wins when both made one with the same counter
+    pub primary: bool,
+}
+
+impl State {
+    /// Loads the state from the given file, or an empty state if the file does not exist
+    pub fn load(file: &str) -> Result<State> {
+        match File::open(file) {
+            Ok(mut file) => {
+                let state: State = serde_json::from_reader(&mut file)?;
+                for (name, profile) in &state.calibration_profiles {
+                    profile
+                        .validate()
+                        .map_err(|e| format_err!("Calibration profile {:?}: {}", name, e))?;
+                }
+                Ok(state)
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, file: &str) -> Result<()> {
+        // Write to a temporary file first, so a crash halfway through does not leave a corrupt state behind
+        let temp_file = format!("{}.tmp", file);
+        serde_json::to_writer_pretty(File::create(&temp_file)?, self)?;
+        fs::rename(&temp_file, file)?;
+        Ok(())
+    }
+
+    /// Records a change, which is newer than both this state and `seen`, the last version of the
+    /// other instance of a redundant pair
+    pub fn touch(&mut self, seen: Version, primary: bool) {
+        self.version = Version {
+            counter: self.version.counter.max(seen.counter) + 1,
+            primary,
+        };
+    }
+
+    pub fn mode_for(&self, client: &Client) -> Option<RenderMode> {
+        client
+            .mac()
+            .and_then(|mac| self.torches.get(mac))
+            .or_else(|| self.torches.get(&client.addr_string))
+            .cloned()
+    }
+
+    pub fn set_mode(&mut self, client: &Client) {
+        let key = client.mac().unwrap_or(&client.addr_string).to_owned();
+        self.torches.insert(key, client.current.clone());
+    }
+}
@@ -0,0 +1,152 @@
+use serde::Serialize;
+
+/// Taps further apart than this many seconds start a new measurement
+const MAX_TAP_INTERVAL: f64 = 2.;
+/// The interval of 1000 BPM, the highest BPM that can be set. Closer taps are a bouncing button.
+const MIN_TAP_INTERVAL: f64 = 0.06;
+/// Amount of taps that are averaged
+const MAX_TAPS: usize = 8;
+
+/// A global beat clock, set by tapping or by entering the BPM directly
+pub struct TempoClock {
+    bpm: f64,
+    /// The time of beat 0, in seconds
+    origin: f64,
+    taps: Vec<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tempo {
+    pub bpm: f64,
+    /// Beats since the clock was last aligned, the fraction is the position within the current beat
+    pub beat: f64,
+}
+
+impl TempoClock {
+    pub fn new(bpm: f64, now: f64) -> TempoClock {
+        TempoClock {
+            bpm,
+            origin: now,
+            taps: Vec::new(),
+        }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    /// Changes the BPM without jumping to a different position within the current beat
+    pub fn set_bpm(&mut self, bpm: f64, now: f64) {
+        let beats = self.beats(now);
+        self.bpm = bpm;
+        self.origin = now - beats * 60. / bpm;
+    }
+
+    /// Every tap lands on a beat, two or more taps in a row set the BPM
+    pub fn tap(&mut self, now: f64) {
+        if let Some(last) = self.taps.last() {
+            if now - last < MIN_TAP_INTERVAL {
+                return;
+            }
+            if now - last > MAX_TAP_INTERVAL {
+                self.taps.clear();
+            }
+        }
+        self.taps.push(now);
+        if self.taps.len() > MAX_TAPS {
+            self.taps.remove(0);
+        }
+        if self.taps.len() >= 2 {
+            let interval = (now - self.taps[0]) / (self.taps.len() - 1) as f64;
+            self.bpm = 60. / interval;
+        }
+        self.origin = now;
+    }
+
+    pub fn beats(&self, now: f64) -> f64 {
+        (now - self.origin) * self.bpm / 60.
+    }
+
+    pub fn tempo(&self, now: f64) -> Tempo {
+        Tempo {
+            bpm: self.bpm,
+            beat: self.beats(now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn taps_set_the_bpm_and_align_the_beat() {
+        let mut clock = TempoClock::new(120., 0.);
+        clock.tap(10.);
+        assert_close(clock.bpm(), 120.);
+        assert_close(clock.beats(10.), 0.);
+        clock.tap(10.5);
+        clock.tap(11.);
+        clock.tap(11.5);
+        assert_close(clock.bpm(), 120.);
+        clock.tap(12.25);
+        // The average over all taps, 2.25 seconds for 4 beats
+        assert_close(clock.bpm(), 4. * 60. / 2.25);
+        assert_close(clock.beats(12.25), 0.);
+    }
+
+    #[test]
+    fn averages_only_the_last_taps() {
+        let mut clock = TempoClock::new(120., 0.);
+        for tap in 0..MAX_TAPS {
+            clock.tap(tap as f64);
+        }
+        assert_close(clock.bpm(), 60.);
+        for tap in 0..MAX_TAPS {
+            clock.tap(MAX_TAPS as f64 - 0.5 + tap as f64 * 0.5);
+        }
+        assert_close(clock.bpm(), 120.);
+    }
+
+    #[test]
+    fn a_long_pause_starts_a_new_measurement() {
+        let mut clock = TempoClock::new(120., 0.);
+        clock.tap(0.);
+        clock.tap(1.);
+        assert_close(clock.bpm(), 60.);
+        clock.tap(1. + MAX_TAP_INTERVAL + 0.1);
+        assert_close(clock.bpm(), 60.);
+        clock.tap(1.5 + MAX_TAP_INTERVAL + 0.1);
+        assert_close(clock.bpm(), 120.);
+    }
+
+    #[test]
+    fn ignores_bouncing_taps() {
+        let mut clock = TempoClock::new(120., 0.);
+        clock.tap(1.);
+        clock.tap(1. + MIN_TAP_INTERVAL / 2.);
+        assert_close(clock.bpm(), 120.);
+        assert_close(clock.beats(1.5), 1.);
+        clock.tap(1.5);
+        assert_close(clock.bpm(), 120.);
+        clock.tap(2.);
+        assert_close(clock.bpm(), 120.);
+    }
+
+    #[test]
+    fn set_bpm_keeps_the_position_within_the_beat() {
+        let mut clock = TempoClock::new(60., 0.);
+        clock.set_bpm(120., 2.5);
+        assert_close(clock.beats(2.5), 2.5);
+        assert_close(clock.beats(3.), 3.5);
+    }
+}
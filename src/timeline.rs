@@ -0,0 +1,163 @@
+use crate::artnet::TimeCode;
+use crate::config::{Action, Cue, TimelineConfig};
+use serde::Serialize;
+
+/// Seconds before a cue whose action failed is tried again, for example after a blackout
+const RETRY_INTERVAL: f64 = 1.;
+
+/// Cues that fire when incoming timecode passes their position.
+///
+/// Only the cue at the current position fires, so jumping around in the timecode restores the
+/// matching look instead of replaying every cue in between.
+pub struct Timeline {
+    cues: Vec<Cue>,
+    locked: bool,
+    timecode: Option<TimeCode>,
+    last_received: f64,
+    current: Option<usize>,
+    /// Set when the action of a cue failed, it is tried again from this time on
+    retry_at: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineStatus {
+    pub locked: bool,
+    /// The last received timecode as `HH:MM:SS:FF`
+    pub timecode: Option<String>,
+    /// Seconds since the last timecode was received
+    pub age: Option<f64>,
+    /// Index of the cue at the current position
+    pub cue: Option<usize>,
+}
+
+impl Timeline {
+    pub fn new(config: TimelineConfig) -> Timeline {
+        let mut cues = config.cues;
+        cues.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap_or(std::cmp::Ordering::Equal));
+        Timeline {
+            cues,
+            locked: config.lock,
+            timecode: None,
+            last_received: 0.,
+            current: None,
+            retry_at: None,
+        }
+    }
+
+    /// Returns the action of the cue that became current, if any
+    pub fn update(&mut self, timecode: TimeCode, now: f64) -> Option<Action> {
+        self.timecode = Some(timecode);
+        self.last_received = now;
+        if !self.locked {
+            return None;
+        }
+        let position = timecode.position();
+        let cue = self.cues.iter().rposition(|c| c.at <= position);
+        if cue == self.current || self.retry_at.is_some_and(|retry_at| now < retry_at) {
+            return None;
+        }
+        self.current = cue;
+        self.retry_at = None;
+        cue.map(|i| self.cues[i].action.clone())
+    }
+
+    /// The action of the current cue could not be applied, so it fires again after a while
+    pub fn cue_failed(&mut self, now: f64) {
+        self.current = None;
+        self.retry_at = Some(now + RETRY_INTERVAL);
+    }
+
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+        // Fire the cue at the current position on the next timecode
+        self.current = None;
+        self.retry_at = None;
+    }
+
+    pub fn status(&self, now: f64) -> TimelineStatus {
+        TimelineStatus {
+            locked: self.locked,
+            timecode: self.timecode.map(|t| t.to_string()),
+            age: self.timecode.map(|_| now - self.last_received),
+            cue: self.current,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline(lock: bool) -> Timeline {
+        let cue = |at, scene: &str| Cue {
+            at,
+            action: Action::Scene(String::from(scene)),
+        };
+        // Out of order, the timeline sorts them
+        Timeline::new(TimelineConfig {
+            lock,
+            cues: vec![cue(20., "chorus"), cue(10., "verse"), cue(30., "outro")],
+        })
+    }
+
+    /// Passes EBU timecode, 25 frames per second, and returns the scene of the cue that fired
+    fn fired(timeline: &mut Timeline, seconds: u8, frames: u8, now: f64) -> Option<String> {
+        let timecode = TimeCode {
+            frames,
+            seconds,
+            minutes: 0,
+            hours: 0,
+            kind: 1,
+        };
+        match timeline.update(timecode, now) {
+            Some(Action::Scene(name)) => Some(name),
+            Some(action) => panic!("Expected a scene, got {:?}", action),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn fires_the_cue_at_the_current_position_once() {
+        let mut timeline = timeline(true);
+        assert_eq!(fired(&mut timeline, 5, 0, 0.), None);
+        assert_eq!(fired(&mut timeline, 10, 0, 0.).as_deref(), Some("verse"));
+        assert_eq!(fired(&mut timeline, 15, 0, 0.), None);
+        assert_eq!(fired(&mut timeline, 19, 24, 0.), None);
+        assert_eq!(fired(&mut timeline, 20, 0, 0.).as_deref(), Some("chorus"));
+        assert_eq!(timeline.status(0.).cue, Some(1));
+    }
+
+    #[test]
+    fn jumps_to_the_cue_at_the_new_position() {
+        let mut timeline = timeline(true);
+        assert_eq!(fired(&mut timeline, 35, 0, 0.).as_deref(), Some("outro"));
+        assert_eq!(fired(&mut timeline, 12, 0, 0.).as_deref(), Some("verse"));
+        // Before the first cue nothing fires, but passing it again does
+        assert_eq!(fired(&mut timeline, 1, 0, 0.), None);
+        assert_eq!(fired(&mut timeline, 10, 0, 0.).as_deref(), Some("verse"));
+    }
+
+    #[test]
+    fn follows_only_while_locked() {
+        let mut timeline = timeline(false);
+        assert_eq!(fired(&mut timeline, 12, 0, 0.), None);
+        assert_eq!(timeline.status(0.).timecode.as_deref(), Some("00:00:12:00"));
+        timeline.set_locked(true);
+        assert_eq!(fired(&mut timeline, 12, 1, 0.).as_deref(), Some("verse"));
+        // Locking again fires the current cue again
+        timeline.set_locked(true);
+        assert_eq!(fired(&mut timeline, 12, 2, 0.).as_deref(), Some("verse"));
+    }
+
+    #[test]
+    fn retries_a_failed_cue() {
+        let mut timeline = timeline(true);
+        assert_eq!(fired(&mut timeline, 10, 0, 0.).as_deref(), Some("verse"));
+        timeline.cue_failed(0.);
+        assert_eq!(timeline.status(0.).cue, None);
+        assert_eq!(fired(&mut timeline, 10, 12, 0.5), None);
+        let retried = fired(&mut timeline, 11, 0, RETRY_INTERVAL);
+        assert_eq!(retried.as_deref(), Some("verse"));
+        assert_eq!(fired(&mut timeline, 11, 12, RETRY_INTERVAL + 0.5), None);
+    }
+}
@@ -1,38 +1,128 @@
 use crate::config::Config;
-use crate::messages::{AddAnimation, RequestAnimationList, RequestNodeList, SetNodeAnimation, SetNodeColor};
+use crate::messages::{
+    AddAnimation, AddFirmware, PushFirmware, RecallScene, RequestAnimationList, RequestCalibration,
+    RequestFirmware, RequestHealth, RequestMetrics, RequestNodeList, RequestNodeMetrics,
+    RequestRecording, RequestTempo, RequestTimeline, SetBlackout, SetBpm, SetCalibration,
+    SetGroupBrightness, SetMasterBrightness, SetNodeAddress, SetNodeAnimation, SetNodeAudio,
+    SetNodeCalibration, SetNodeColor, SetNodeExternal, SetNodeIp, SetNodeTempoAnimation,
+    SetTimecodeLock, StartRecording, StopRecording, TapTempo,
+};
+use crate::metrics::{self, ApiMetrics};
 use crate::service;
 use actix::{Addr, Recipient};
 use actix_files::NamedFile;
 use actix_http::http;
 use actix_multipart::Multipart;
-use actix_web::dev::Server;
-use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use actix_web::dev::{HttpServiceFactory, Server, Service};
+use actix_web::{web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Resource};
 use failure::Error;
 use futures::{future, Future, Stream};
 use serde::Serialize;
 use serde_json;
+use std::sync::{Arc, Mutex};
 
 pub struct ServerState {
     pub request_node_list: Recipient<RequestNodeList>,
+    pub request_node_metrics: Recipient<RequestNodeMetrics>,
+    pub request_metrics: Recipient<RequestMetrics>,
+    /// Shared by the workers, filled by the middleware in `run`
+    pub api_metrics: Arc<Mutex<ApiMetrics>>,
     pub request_animation_list: Recipient<RequestAnimationList>,
     pub add_animation: Recipient<AddAnimation>,
+    pub add_firmware: Recipient<AddFirmware>,
+    pub request_firmware: Recipient<RequestFirmware>,
+    pub push_firmware: Recipient<PushFirmware>,
     pub set_node_animation: Recipient<SetNodeAnimation>,
     pub set_node_color: Recipient<SetNodeColor>,
+    pub set_master_brightness: Recipient<SetMasterBrightness>,
+    pub request_calibration: Recipient<RequestCalibration>,
+    pub set_calibration: Recipient<SetCalibration>,
+    pub set_node_calibration: Recipient<SetNodeCalibration>,
+    pub set_blackout: Recipient<SetBlackout>,
+    pub set_node_audio: Recipient<SetNodeAudio>,
+    pub request_tempo: Recipient<RequestTempo>,
+    pub tap_tempo: Recipient<TapTempo>,
+    pub set_bpm: Recipient<SetBpm>,
+    pub set_node_tempo_animation: Recipient<SetNodeTempoAnimation>,
+    pub set_node_external: Recipient<SetNodeExternal>,
+    pub request_timeline: Recipient<RequestTimeline>,
+    pub set_node_address: Recipient<SetNodeAddress>,
+    pub set_node_ip: Recipient<SetNodeIp>,
+    pub set_timecode_lock: Recipient<SetTimecodeLock>,
+    pub request_health: Recipient<RequestHealth>,
+    pub request_recording: Recipient<RequestRecording>,
+    pub start_recording: Recipient<StartRecording>,
+    pub stop_recording: Recipient<StopRecording>,
+    pub set_group_brightness: Recipient<SetGroupBrightness>,
+    pub recall_scene: Recipient<RecallScene>,
 }
 
 impl ServerState {
-    pub fn new(addr: &Addr<service::Service>) -> Self {
+    pub fn new(addr: &Addr<service::Service>, api_metrics: Arc<Mutex<ApiMetrics>>) -> Self {
         let request_node_list = addr.clone().recipient();
+        let request_node_metrics = addr.clone().recipient();
+        let request_metrics = addr.clone().recipient();
         let request_animation_list = addr.clone().recipient();
         let add_animation = addr.clone().recipient();
+        let add_firmware = addr.clone().recipient();
+        let request_firmware = addr.clone().recipient();
+        let push_firmware = addr.clone().recipient();
         let set_node_animation = addr.clone().recipient();
         let set_node_color = addr.clone().recipient();
+        let set_master_brightness = addr.clone().recipient();
+        let request_calibration = addr.clone().recipient();
+        let set_calibration = addr.clone().recipient();
+        let set_node_calibration = addr.clone().recipient();
+        let set_blackout = addr.clone().recipient();
+        let set_node_audio = addr.clone().recipient();
+        let request_tempo = addr.clone().recipient();
+        let tap_tempo = addr.clone().recipient();
+        let set_bpm = addr.clone().recipient();
+        let set_node_tempo_animation = addr.clone().recipient();
+        let set_node_external = addr.clone().recipient();
+        let request_timeline = addr.clone().recipient();
+        let set_node_address = addr.clone().recipient();
+        let set_node_ip = addr.clone().recipient();
+        let set_timecode_lock = addr.clone().recipient();
+        let request_health = addr.clone().recipient();
+        let request_recording = addr.clone().recipient();
+        let start_recording = addr.clone().recipient();
+        let stop_recording = addr.clone().recipient();
+        let set_group_brightness = addr.clone().recipient();
+        let recall_scene = addr.clone().recipient();
         ServerState {
             request_node_list,
+            request_node_metrics,
+            request_metrics,
+            api_metrics,
             request_animation_list,
             add_animation,
+            add_firmware,
+            request_firmware,
+            push_firmware,
             set_node_animation,
             set_node_color,
+            set_master_brightness,
+            request_calibration,
+            set_calibration,
+            set_node_calibration,
+            set_blackout,
+            set_node_audio,
+            request_tempo,
+            tap_tempo,
+            set_bpm,
+            set_node_tempo_animation,
+            set_node_external,
+            request_timeline,
+            set_node_address,
+            set_node_ip,
+            set_timecode_lock,
+            request_health,
+            request_recording,
+            start_recording,
+            stop_recording,
+            set_group_brightness,
+            recall_scene,
         }
     }
 }
@@ -67,6 +157,38 @@ fn handler_request_node_list(req: HttpRequest) -> Response {
             .or_else(|e| Ok(err(&e.into()))),
     )
 }
+fn handler_request_node_metrics(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .request_node_metrics
+            .send(RequestNodeMetrics)
+            .map(|response| match response {
+                Ok(r) => json(r.nodes),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+fn handler_metrics(req: HttpRequest) -> Response {
+    let state = req.app_data::<ServerState>().unwrap();
+    let api_metrics = state.api_metrics.clone();
+    Box::new(
+        state
+            .request_metrics
+            .send(RequestMetrics)
+            .map(move |response| match response {
+                Ok(r) => {
+                    let api_metrics = api_metrics.lock().expect("Poisoned");
+                    HttpResponse::Ok()
+                        .content_type("text/plain; version=0.0.4")
+                        .body(metrics::render(&r, &api_metrics))
+                }
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
 fn handler_request_animation_list(req: HttpRequest) -> Response {
     Box::new(
         req.app_data::<ServerState>()
@@ -118,6 +240,385 @@ fn handler_set_node_color(
     )
 }
 
+fn handler_set_master_brightness((req, param): (HttpRequest, web::Path<u8>)) -> Response {
+    let brightness = *param;
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_master_brightness
+            .send(SetMasterBrightness { brightness })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_request_calibration(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .request_calibration
+            .send(RequestCalibration)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_calibration(
+    (req, param): (HttpRequest, web::Path<(String, String, String)>),
+) -> Response {
+    let profile = param.0.clone();
+    let setting = param.1.clone();
+    let values = param.2.clone();
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_calibration
+            .send(SetCalibration {
+                profile,
+                setting,
+                values,
+            })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_node_calibration(
+    (req, param): (HttpRequest, web::Path<(String, String)>),
+) -> Response {
+    let ip = param.0.clone();
+    let profile = param.1.clone();
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_node_calibration
+            .send(SetNodeCalibration { ip, profile })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_group_brightness((req, param): (HttpRequest, web::Path<(String, u8)>)) -> Response {
+    let group = param.0.clone();
+    let brightness = param.1;
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_group_brightness
+            .send(SetGroupBrightness { group, brightness })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_recall_scene((req, param): (HttpRequest, web::Path<String>)) -> Response {
+    let name = param.clone();
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .recall_scene
+            .send(RecallScene { name })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_blackout(req: HttpRequest) -> Response {
+    send_blackout(&req, true)
+}
+
+fn handler_release_blackout(req: HttpRequest) -> Response {
+    send_blackout(&req, false)
+}
+
+fn send_blackout(req: &HttpRequest, latched: bool) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_blackout
+            .send(SetBlackout { latched })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_node_audio((req, param): (HttpRequest, web::Path<(String, String)>)) -> Response {
+    let ip = param.0.clone();
+    let mode = param.1.clone();
+    send_node_audio(
+        &req,
+        SetNodeAudio {
+            ip,
+            mode,
+            value: None,
+        },
+    )
+}
+
+fn handler_set_node_audio_value(
+    (req, param): (HttpRequest, web::Path<(String, String, String)>),
+) -> Response {
+    let ip = param.0.clone();
+    let mode = param.1.clone();
+    let value = Some(param.2.clone());
+    send_node_audio(&req, SetNodeAudio { ip, mode, value })
+}
+
+fn send_node_audio(req: &HttpRequest, audio: SetNodeAudio) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_node_audio
+            .send(audio)
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_request_tempo(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .request_tempo
+            .send(RequestTempo)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_request_timeline(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .request_timeline
+            .send(RequestTimeline)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_timecode_lock(req: HttpRequest) -> Response {
+    send_timecode_lock(&req, true)
+}
+
+fn handler_timecode_unlock(req: HttpRequest) -> Response {
+    send_timecode_lock(&req, false)
+}
+
+fn send_timecode_lock(req: &HttpRequest, locked: bool) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_timecode_lock
+            .send(SetTimecodeLock { locked })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_request_health(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .request_health
+            .send(RequestHealth)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_request_recording(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .request_recording
+            .send(RequestRecording)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_start_recording(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .start_recording
+            .send(StartRecording)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_stop_recording(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .stop_recording
+            .send(StopRecording)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_tap_tempo(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .tap_tempo
+            .send(TapTempo)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_bpm((req, param): (HttpRequest, web::Path<f64>)) -> Response {
+    let bpm = *param;
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_bpm
+            .send(SetBpm { bpm })
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_node_tempo_animation(
+    (req, param): (HttpRequest, web::Path<(String, String, u8)>),
+) -> Response {
+    let ip = param.0.clone();
+    let animation_name = param.1.clone();
+    let frames_per_beat = param.2;
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_node_tempo_animation
+            .send(SetNodeTempoAnimation {
+                ip,
+                animation_name,
+                frames_per_beat,
+            })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_node_external((req, param): (HttpRequest, web::Path<(String, u16)>)) -> Response {
+    let ip = param.0.clone();
+    let universe = param.1;
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_node_external
+            .send(SetNodeExternal { ip, universe })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_node_address(
+    (req, param): (HttpRequest, web::Path<(String, String, String)>),
+) -> Response {
+    let ip = param.0.clone();
+    let setting = param.1.clone();
+    let value = param.2.clone();
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_node_address
+            .send(SetNodeAddress { ip, setting, value })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_set_node_dhcp((req, param): (HttpRequest, web::Path<String>)) -> Response {
+    let ip = param.clone();
+    send_node_ip(&req, SetNodeIp { ip, address: None })
+}
+
+fn handler_set_node_ip(
+    (req, param): (HttpRequest, web::Path<(String, String, String)>),
+) -> Response {
+    let ip = param.0.clone();
+    let address = Some((param.1.clone(), param.2.clone()));
+    send_node_ip(&req, SetNodeIp { ip, address })
+}
+
+fn send_node_ip(req: &HttpRequest, message: SetNodeIp) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .set_node_ip
+            .send(message)
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
 #[derive(Debug)]
 enum UploadItem {
     Form { name: String, value: String },
@@ -220,23 +721,207 @@ fn handler_add_animation(
     )
 }
 
+fn handler_add_firmware((req, multipart): (HttpRequest, Multipart)) -> Response {
+    Box::new(
+        multipart
+            .map_err(|e| format_err!("Multipart error: {:?}", e))
+            .and_then(map_multipart_field)
+            .filter_map(|e| e)
+            .collect()
+            .map(move |params| {
+                let name = params.iter().find_map(|f| f.get_formdata_name());
+                let data = params.iter().find_map(|f| f.get_file_data());
+
+                if let (Some(name), Some(data)) = (name, data) {
+                    future::Either::A(
+                        req.app_data::<ServerState>()
+                            .unwrap()
+                            .add_firmware
+                            .send(AddFirmware {
+                                name: name.to_owned(),
+                                bytes: data.to_vec(),
+                            })
+                            .map(|e| match e {
+                                Ok(_) => str(String::from("ok")),
+                                Err(e) => err(&e),
+                            })
+                            .or_else(|e| future::ok(err(&e.into()))),
+                    )
+                } else {
+                    future::Either::B(future::ok(str("Invalid form data".to_owned())))
+                }
+            })
+            .and_then(|e| e),
+    )
+}
+
+fn handler_request_firmware(req: HttpRequest) -> Response {
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .request_firmware
+            .send(RequestFirmware)
+            .map(|response| match response {
+                Ok(r) => json(r),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+fn handler_push_firmware((req, param): (HttpRequest, web::Path<(String, String)>)) -> Response {
+    let firmware = param.0.clone();
+    let torches = param.1.clone();
+    Box::new(
+        req.app_data::<ServerState>()
+            .unwrap()
+            .push_firmware
+            .send(PushFirmware { firmware, torches })
+            .map(|v| match v {
+                Ok(_) => str(String::from("ok")),
+                Err(e) => err(&e),
+            })
+            .or_else(|e| Ok(err(&e.into()))),
+    )
+}
+
+/// The pattern of the route a request matched, so the IPs and names in paths do not each get
+/// their own metrics. Stored in the request by `resource`.
+struct RoutePattern(String);
+
+/// A resource that stores its pattern in the requests it matches, with `routes` added to it.
+/// The regexes of the parameters are left out, so `{ip:[\\w\\.]+}` becomes `{ip}`.
+fn resource<F>(pattern: &'static str, routes: F) -> impl HttpServiceFactory
+where
+    F: FnOnce(Resource) -> Resource,
+{
+    let mut label = String::new();
+    let (mut in_parameter, mut in_regex) = (false, false);
+    for c in pattern.chars() {
+        match c {
+            '{' => in_parameter = true,
+            ':' if in_parameter => in_regex = true,
+            '}' => {
+                in_parameter = false;
+                in_regex = false;
+            }
+            _ => {}
+        }
+        if !in_regex {
+            label.push(c);
+        }
+    }
+    routes(web::resource(pattern)).wrap_fn(move |req, srv| {
+        req.extensions_mut().insert(RoutePattern(label.clone()));
+        srv.call(req)
+    })
+}
+
+fn route_pattern(req: &HttpRequest) -> String {
+    req.extensions()
+        .get::<RoutePattern>()
+        .map_or_else(|| String::from("unmatched"), |route| route.0.clone())
+}
+
 pub fn run(addr: &Addr<service::Service>) -> Server {
     let config = Config::from_file("config.json").expect("Could not load config");
     let addr = addr.clone();
+    let api_metrics = Arc::new(Mutex::new(ApiMetrics::default()));
     let result = HttpServer::new(move || {
+        let metrics = api_metrics.clone();
         App::new()
-            .data(ServerState::new(&addr))
-            .service(web::resource("/").to(index))
-            .service(web::resource("/api/nodes").to(handler_request_node_list))
-            .service(web::resource("/api/animations").to(handler_request_animation_list))
-            .service(
-                web::resource("/api/set_animation/{ip:[\\w\\.]+}/{animation}")
-                    .to(handler_set_node_animation),
-            )
-            .service(
-                web::resource("/api/set_color/{ip:[\\w\\.]+}/{color}").to(handler_set_node_color),
-            )
-            .service(web::resource("/api/animation/{name}").to(handler_add_animation))
+            .data(ServerState::new(&addr, api_metrics.clone()))
+            .wrap_fn(move |req, srv| {
+                let start = time::precise_time_s();
+                let metrics = metrics.clone();
+                srv.call(req).map(move |res| {
+                    let route = route_pattern(res.request());
+                    metrics.lock().expect("Poisoned").record(
+                        res.request().method().as_str(),
+                        route,
+                        res.status().as_u16(),
+                        time::precise_time_s() - start,
+                    );
+                    res
+                })
+            })
+            .service(resource("/", |r| r.to(index)))
+            .service(resource("/metrics", |r| r.to(handler_metrics)))
+            .service(resource("/api/nodes", |r| r.to(handler_request_node_list)))
+            .service(resource("/api/nodes/metrics", |r| r.to(handler_request_node_metrics)))
+            .service(resource("/api/animations", |r| r.to(handler_request_animation_list)))
+            .service(resource(
+                "/api/set_animation/{ip:[\\w\\.]+}/{animation}",
+                |r| r.to(handler_set_node_animation),
+            ))
+            .service(resource(
+                "/api/set_color/{ip:[\\w\\.]+}/{color}",
+                |r| r.to(handler_set_node_color),
+            ))
+            .service(resource("/api/animation/{name}", |r| r.to(handler_add_animation)))
+            .service(resource("/api/firmware", |r| {
+                r.route(web::get().to(handler_request_firmware))
+                    .route(web::post().to(handler_add_firmware))
+            }))
+            .service(resource(
+                "/api/firmware/{name}/push/{torches}",
+                |r| r.to(handler_push_firmware),
+            ))
+            .service(resource(
+                "/api/brightness/{brightness}",
+                |r| r.to(handler_set_master_brightness),
+            ))
+            .service(resource(
+                "/api/group/{group}/brightness/{brightness}",
+                |r| r.to(handler_set_group_brightness),
+            ))
+            .service(resource("/api/scene/{name}", |r| r.to(handler_recall_scene)))
+            .service(resource("/api/calibration", |r| r.to(handler_request_calibration)))
+            .service(resource(
+                "/api/set_audio/{ip:[\\w\\.]+}/{mode}",
+                |r| r.to(handler_set_node_audio),
+            ))
+            .service(resource(
+                "/api/set_audio/{ip:[\\w\\.]+}/{mode}/{value}",
+                |r| r.to(handler_set_node_audio_value),
+            ))
+            .service(resource(
+                "/api/set_tempo_animation/{ip:[\\w\\.]+}/{animation}/{frames_per_beat}",
+                |r| r.to(handler_set_node_tempo_animation),
+            ))
+            .service(resource(
+                "/api/set_external/{ip:[\\w\\.]+}/{universe}",
+                |r| r.to(handler_set_node_external),
+            ))
+            .service(resource("/api/tempo", |r| r.to(handler_request_tempo)))
+            .service(resource("/api/tempo/tap", |r| r.to(handler_tap_tempo)))
+            .service(resource("/api/tempo/bpm/{bpm}", |r| r.to(handler_set_bpm)))
+            .service(resource(
+                "/api/address/{ip:[\\w\\.]+}/{setting}/{value}",
+                |r| r.to(handler_set_node_address),
+            ))
+            .service(resource("/api/ip_prog/{ip:[\\w\\.]+}/dhcp", |r| r.to(handler_set_node_dhcp)))
+            .service(resource(
+                "/api/ip_prog/{ip:[\\w\\.]+}/{address:[\\w\\.]+}/{mask:[\\w\\.]+}",
+                |r| r.to(handler_set_node_ip),
+            ))
+            .service(resource("/api/timeline", |r| r.to(handler_request_timeline)))
+            .service(resource("/api/timeline/lock", |r| r.to(handler_timecode_lock)))
+            .service(resource("/api/timeline/unlock", |r| r.to(handler_timecode_unlock)))
+            .service(resource("/api/health", |r| r.to(handler_request_health)))
+            .service(resource("/api/recording", |r| r.to(handler_request_recording)))
+            .service(resource("/api/recording/start", |r| r.to(handler_start_recording)))
+            .service(resource("/api/recording/stop", |r| r.to(handler_stop_recording)))
+            .service(resource("/api/blackout", |r| r.to(handler_blackout)))
+            .service(resource("/api/blackout/release", |r| r.to(handler_release_blackout)))
+            .service(resource(
+                "/api/calibration/{profile}/{setting}/{values}",
+                |r| r.to(handler_set_calibration),
+            ))
+            .service(resource(
+                "/api/set_calibration/{ip:[\\w\\.]+}/{profile}",
+                |r| r.to(handler_set_node_calibration),
+            ))
     })
     .bind(config.web_endpoint)
     .expect("Could not bind web API")
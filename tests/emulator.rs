@@ -0,0 +1,234 @@
+//! Runs the service against emulated torches on loopback IPs.
+//!
+//! The service binds port 6454 like the server does, so a server running on the same machine
+//! can take some of the replies away.
+
+use actix::{Addr, System};
+use futures::Future;
+use reality_lights::artnet::FIRMWARE_BLOCK_SIZE;
+use reality_lights::config::Config;
+use reality_lights::emulator::{Emulator, Options, Stats};
+use reality_lights::firmware::UploadState;
+use reality_lights::messages::{
+    AddFirmware, PushFirmware, RequestFirmware, RequestNodeList, SetNodeColor,
+};
+use reality_lights::service::Service;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{mpsc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Discovery takes a poll, which goes out every second
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Every test binds port 6454, so they run one at a time
+static PORT: Mutex<()> = Mutex::new(());
+
+/// The service running on its own actix system
+struct Server {
+    addr: Addr<Service>,
+    system: System,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    fn start(broadcasts: Vec<SocketAddr>) -> Server {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "web_endpoint": "127.0.0.1:0",
+            "broadcasts": broadcasts,
+            "torch_mappings": [],
+        }))
+        .expect("Invalid config");
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let system = System::new("test");
+            let addr = Service::start_with(config);
+            sender
+                .send((addr, System::current()))
+                .expect("Test stopped");
+            let _ = system.run();
+        });
+        let (addr, system) = receiver.recv().expect("Service did not start");
+        Server {
+            addr,
+            system,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.system.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Takes the port and runs in a directory of its own, so no state, animations or firmware of a
+/// previous run are loaded
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = PORT.lock().unwrap_or_else(|e| e.into_inner());
+    let directory =
+        std::env::temp_dir().join(format!("reality_lights_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).expect("Could not create test directory");
+    std::env::set_current_dir(&directory).expect("Could not enter test directory");
+    guard
+}
+
+fn emulate(torches: u8, first_ip: Ipv4Addr) -> Emulator {
+    Emulator::start(Options {
+        torches,
+        first_ip,
+        ..Options::default()
+    })
+    .expect("Could not start emulator")
+}
+
+fn wait_until<T>(what: &str, mut check: impl FnMut() -> Option<T>) -> T {
+    let start = Instant::now();
+    loop {
+        if let Some(value) = check() {
+            return value;
+        }
+        if start.elapsed() > TIMEOUT {
+            panic!("Timed out waiting for {}", what);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn stats<T>(emulator: &Emulator, check: impl Fn(&Stats) -> T) -> Vec<T> {
+    emulator
+        .torches
+        .iter()
+        .map(|(_, stats)| check(&stats.lock().expect("Poisoned")))
+        .collect()
+}
+
+#[test]
+fn discovers_torches_and_sends_frames() {
+    let _guard = setup();
+    let emulator = emulate(2, Ipv4Addr::new(127, 0, 0, 40));
+    let server = Server::start(emulator.broadcasts());
+
+    let nodes = wait_until("discovery", || {
+        let nodes = server.addr.send(RequestNodeList).wait().ok()?.ok()?.nodes;
+        if nodes.len() == 2 {
+            Some(nodes)
+        } else {
+            None
+        }
+    });
+    let mut names: Vec<(&str, &str)> = nodes
+        .iter()
+        .map(|node| (node.ip.as_str(), node.short_name.as_str()))
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [("127.0.0.40", "Emulated 1"), ("127.0.0.41", "Emulated 2")]
+    );
+
+    // The default mode is green, limited to the default maximum of 100 per channel
+    wait_until("the default mode", || {
+        let pixels = stats(&emulator, |stats| (stats.frames, stats.pixel));
+        if pixels
+            .iter()
+            .all(|(frames, pixel)| *frames > 0 && *pixel == (0, 100, 0))
+        {
+            Some(())
+        } else {
+            None
+        }
+    });
+
+    server
+        .addr
+        .send(SetNodeColor {
+            ip: String::from("127.0.0.41"),
+            color_name: String::from("ff0000"),
+        })
+        .wait()
+        .expect("Service stopped")
+        .expect("Could not set color");
+    wait_until("the new color", || {
+        match stats(&emulator, |stats| stats.pixel).as_slice() {
+            [(0, 100, 0), (100, 0, 0)] => Some(()),
+            _ => None,
+        }
+    });
+}
+
+#[test]
+fn pushes_firmware() {
+    let _guard = setup();
+    let emulator = emulate(2, Ipv4Addr::new(127, 0, 0, 50));
+    let server = Server::start(emulator.broadcasts());
+    wait_until("discovery", || {
+        let nodes = server.addr.send(RequestNodeList).wait().ok()?.ok()?.nodes;
+        if nodes.len() == 2 {
+            Some(())
+        } else {
+            None
+        }
+    });
+
+    // Three blocks, the last one partially filled
+    let image = vec![0xAB; FIRMWARE_BLOCK_SIZE * 2 + 100];
+    let send = |result: Result<reality_lights::Result<()>, actix::MailboxError>| {
+        result.expect("Service stopped")
+    };
+    send(
+        server
+            .addr
+            .send(AddFirmware {
+                name: String::from("test"),
+                bytes: image,
+            })
+            .wait(),
+    )
+    .expect("Could not add firmware");
+    send(
+        server
+            .addr
+            .send(PushFirmware {
+                firmware: String::from("test"),
+                torches: String::from("127.0.0.40"),
+            })
+            .wait(),
+    )
+    .expect_err("Pushed to a torch that does not exist");
+    send(
+        server
+            .addr
+            .send(PushFirmware {
+                firmware: String::from("test"),
+                torches: String::from("all"),
+            })
+            .wait(),
+    )
+    .expect("Could not push firmware");
+
+    let uploads = wait_until("the uploads", || {
+        let uploads = server.addr.send(RequestFirmware).wait().ok()?.ok()?.uploads;
+        if uploads.len() == 2
+            && uploads
+                .iter()
+                .all(|upload| matches!(upload.state, UploadState::Done))
+        {
+            Some(uploads)
+        } else {
+            None
+        }
+    });
+    assert!(uploads
+        .iter()
+        .all(|upload| upload.blocks == 3 && upload.blocks_confirmed == 3));
+    assert_eq!(stats(&emulator, |stats| stats.firmware_blocks), [3, 3]);
+    // The torches reboot into the new firmware
+    assert_eq!(stats(&emulator, |stats| stats.reboots), [1, 1]);
+}